@@ -0,0 +1,79 @@
+use fuels::accounts::provider::Provider;
+use log::info;
+use pangea_client::ChainId;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::env::ev;
+use crate::error::Error;
+
+/// Сеть Fuel, с которой работает процесс. Задается через `CHAIN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    Mainnet,
+    Testnet,
+    /// Локальная `fuel-core` нода для тестов и стейджинга.
+    Devnet,
+}
+
+impl Chain {
+    /// Читает `CHAIN`. Если переменная не задана — `None`,
+    /// неизвестное значение — ошибка, чтобы не уйти молча в другую сеть.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        match ev("CHAIN") {
+            Ok(value) => value.parse().map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Адрес ноды: `FUEL_<CHAIN>_NODE_URL` или адрес по умолчанию.
+    pub fn node_url(&self) -> String {
+        let (key, default) = match self {
+            Chain::Mainnet => ("FUEL_MAINNET_NODE_URL", "mainnet.fuel.network"),
+            Chain::Testnet => ("FUEL_TESTNET_NODE_URL", "testnet.fuel.network"),
+            Chain::Devnet => ("FUEL_DEVNET_NODE_URL", "127.0.0.1:4000"),
+        };
+        ev(key).unwrap_or_else(|_| default.to_string())
+    }
+
+    pub async fn connect(&self) -> Result<Provider, Error> {
+        let url = self.node_url();
+        let provider = Provider::connect(&url).await?;
+        info!("Connected to {} Fuel node at {}", self, url);
+        Ok(provider)
+    }
+
+    /// Идентификатор сети в Pangea. Локальную сеть Pangea не индексирует.
+    pub fn pangea_chain_id(&self) -> Result<ChainId, Error> {
+        match self {
+            Chain::Mainnet => Ok(ChainId::FUEL),
+            Chain::Testnet => Ok(ChainId::FUELTESTNET),
+            Chain::Devnet => Err(Error::ConfigError(
+                "Pangea does not index the devnet chain, use the fuel-node indexer".to_string(),
+            )),
+        }
+    }
+}
+
+impl FromStr for Chain {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "FUEL" | "MAINNET" => Ok(Chain::Mainnet),
+            "FUELTESTNET" | "TESTNET" => Ok(Chain::Testnet),
+            "DEVNET" | "LOCAL" => Ok(Chain::Devnet),
+            _ => Err(Error::UnknownChain(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Chain::Mainnet => "mainnet",
+            Chain::Testnet => "testnet",
+            Chain::Devnet => "devnet",
+        })
+    }
+}
@@ -0,0 +1,242 @@
+use ethers_core::types::H256;
+use fuels::accounts::provider::Provider;
+use fuels::accounts::wallet::WalletUnlocked;
+use fuels::types::ContractId;
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use spark_market_sdk::SparkMarketContract;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+
+use crate::config::chain::Chain;
+use crate::config::env::ev;
+use crate::config::markets::Markets;
+use crate::error::Error;
+
+const DEFAULT_METADATA_CACHE: &str = "market_metadata.json";
+
+/// Ступень протокольной комиссии: действует от `volume_threshold` объема.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FeeTier {
+    pub volume_threshold: u64,
+    pub maker_fee: u64,
+    pub taker_fee: u64,
+}
+
+/// Параметры рынка, прочитанные из Spark контракта.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MarketMetadata {
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub base_decimals: u32,
+    pub quote_decimals: u32,
+    pub price_decimals: u32,
+    pub matcher_fee: u64,
+    pub protocol_fee: Vec<FeeTier>,
+}
+
+/// Содержимое `MARKET_METADATA_CACHE`, ключи — id рынков.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MetadataCache {
+    #[serde(default)]
+    markets: HashMap<String, MarketMetadata>,
+    /// Символы, под которыми рынки уже индексировались.
+    #[serde(default)]
+    symbols: HashMap<String, String>,
+}
+
+/// Читает метаданные рынков из контрактов и применяет их к `markets`.
+/// Если контракт недоступен, берется последний закэшированный ответ из
+/// `MARKET_METADATA_CACHE`, а без него остаются значения из конфига.
+/// Символ, под которым рынок уже индексировался, не меняется.
+pub async fn load_market_metadata(markets: &mut Markets, chain: Chain) -> Result<(), Error> {
+    let cache_path = ev("MARKET_METADATA_CACHE").unwrap_or_else(|_| DEFAULT_METADATA_CACHE.to_string());
+    let provider = match chain.connect().await {
+        Ok(provider) => Some(provider),
+        Err(e) => {
+            warn!("Failed to connect to Fuel node for market metadata: {}", e);
+            None
+        }
+    };
+    apply_market_metadata(markets, provider, &cache_path).await
+}
+
+async fn apply_market_metadata(
+    markets: &mut Markets,
+    provider: Option<Provider>,
+    cache_path: &str,
+) -> Result<(), Error> {
+    let mut cache = read_cache(cache_path);
+    // Только для read-only вызовов, транзакции этим кошельком не подписываются
+    let wallet = provider.map(|provider| WalletUnlocked::new_random(Some(provider)));
+
+    let ids: Vec<H256> = markets.iter().map(|m| m.id).collect();
+    for id in ids {
+        let key = format!("{:?}", id);
+        if let Some(symbol) = cache.symbols.get(&key) {
+            markets.pin_symbol(id, symbol);
+        }
+        if let Some(wallet) = &wallet {
+            match fetch_metadata(id, wallet).await {
+                Ok(metadata) => {
+                    cache.markets.insert(key.clone(), metadata);
+                }
+                Err(e) => warn!("Failed to read metadata of market {} from contract: {}", key, e),
+            }
+        }
+
+        match cache.markets.get(&key) {
+            Some(metadata) => {
+                if let Err(e) = markets.apply_metadata(id, metadata.clone()) {
+                    warn!("Rejected metadata of market {}: {}. Using configured decimals", key, e);
+                }
+            }
+            None => warn!("No metadata for market {}, using configured decimals", key),
+        }
+    }
+
+    for market in markets.iter() {
+        cache.symbols.insert(format!("{:?}", market.id), market.symbol.clone());
+        info!(
+            "Market {} ({:?}): base {} decimals, quote {} decimals, price {} decimals",
+            market.symbol, market.id, market.base_decimals, market.quote_decimals, market.price_decimals
+        );
+    }
+
+    if let Err(e) = fs::write(cache_path, serde_json::to_vec_pretty(&cache)?) {
+        warn!("Failed to write market metadata cache {}: {}", cache_path, e);
+    }
+    Ok(())
+}
+
+async fn fetch_metadata(id: H256, wallet: &WalletUnlocked) -> Result<MarketMetadata, Error> {
+    let contract = SparkMarketContract::new(ContractId::new(id.0), wallet.clone()).await;
+    let (base_asset, base_decimals, quote_asset, quote_decimals, _owner, price_decimals, _version) =
+        contract.config().await?.value;
+    let matcher_fee = contract.matcher_fee().await?.value;
+    let protocol_fee = contract
+        .protocol_fee()
+        .await?
+        .value
+        .into_iter()
+        .map(|fee| FeeTier {
+            volume_threshold: fee.volume_threshold,
+            maker_fee: fee.maker_fee,
+            taker_fee: fee.taker_fee,
+        })
+        .collect();
+
+    Ok(MarketMetadata {
+        base_asset: format!("0x{}", hex::encode(base_asset)),
+        quote_asset: format!("0x{}", hex::encode(quote_asset)),
+        base_decimals,
+        quote_decimals,
+        price_decimals,
+        matcher_fee,
+        protocol_fee,
+    })
+}
+
+fn read_cache(path: &str) -> MetadataCache {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return MetadataCache::default(),
+        Err(e) => {
+            warn!("Failed to read market metadata cache {}: {}", path, e);
+            return MetadataCache::default();
+        }
+    };
+    serde_json::from_slice(&content).unwrap_or_else(|e| {
+        warn!("Corrupted market metadata cache {}: {}", path, e);
+        MetadataCache::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_node::{TestMarket, BASE_DECIMALS, PRICE_DECIMALS, QUOTE_DECIMALS};
+
+    fn cache_path() -> String {
+        std::env::temp_dir()
+            .join(format!("market-metadata-test-{}.json", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[tokio::test]
+    async fn reads_decimals_and_assets_from_contract() {
+        let node = TestMarket::deploy().await;
+        let mut markets = node.markets(None);
+        let path = cache_path();
+
+        apply_market_metadata(&mut markets, Some(node.provider.clone()), &path)
+            .await
+            .unwrap();
+
+        let market = markets.iter().next().unwrap();
+        assert_eq!(market.base_decimals, BASE_DECIMALS);
+        assert_eq!(market.quote_decimals, QUOTE_DECIMALS);
+        assert_eq!(market.price_decimals, PRICE_DECIMALS);
+        let metadata = market.metadata.as_ref().unwrap();
+        assert_eq!(metadata.base_asset, format!("0x{}", hex::encode(node.base_asset)));
+        assert_eq!(metadata.quote_asset, format!("0x{}", hex::encode(node.quote_asset)));
+        assert_eq!(market.symbol, "0x01010101/0x02020202");
+
+        // Без ноды метаданные берутся из кэша
+        let mut offline = node.markets(None);
+        apply_market_metadata(&mut offline, None, &path).await.unwrap();
+        let market = offline.iter().next().unwrap();
+        assert_eq!(market.quote_decimals, QUOTE_DECIMALS);
+        assert_eq!(market.symbol, "0x01010101/0x02020202");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn keeps_symbol_market_was_indexed_under() {
+        let node = TestMarket::deploy().await;
+        let path = cache_path();
+
+        // Первый запуск без ноды: рынок индексируется под временным именем по id
+        let mut markets = node.markets(None);
+        apply_market_metadata(&mut markets, None, &path).await.unwrap();
+        let indexed_symbol = markets.iter().next().unwrap().symbol.clone();
+        assert!(indexed_symbol.starts_with("0x"));
+
+        let mut markets = node.markets(None);
+        apply_market_metadata(&mut markets, Some(node.provider.clone()), &path)
+            .await
+            .unwrap();
+        let market = markets.iter().next().unwrap();
+        assert_eq!(market.symbol, indexed_symbol);
+        assert_eq!(market.quote_decimals, QUOTE_DECIMALS);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn rejects_metadata_with_too_many_decimals() {
+        let node = TestMarket::deploy().await;
+        let path = cache_path();
+        let mut markets = node.markets(Some("BASE/QUOTE"));
+        apply_market_metadata(&mut markets, Some(node.provider.clone()), &path)
+            .await
+            .unwrap();
+
+        // Испорченный кэш при недоступной ноде не должен переполнить pricescale
+        let mut cache = read_cache(&path);
+        for metadata in cache.markets.values_mut() {
+            metadata.price_decimals = 30;
+        }
+        fs::write(&path, serde_json::to_vec(&cache).unwrap()).unwrap();
+
+        let mut markets = node.markets(Some("BASE/QUOTE"));
+        apply_market_metadata(&mut markets, None, &path).await.unwrap();
+        let market = markets.iter().next().unwrap();
+        assert_eq!(market.price_decimals, 9);
+        assert!(market.metadata.is_none());
+        assert_eq!(market.pricescale(), 10u64.pow(9));
+        let _ = fs::remove_file(&path);
+    }
+}
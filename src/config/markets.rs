@@ -0,0 +1,255 @@
+use ethers_core::types::H256;
+use log::warn;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::str::FromStr;
+
+use crate::config::env::ev;
+use crate::config::market_metadata::MarketMetadata;
+use crate::error::Error;
+
+/// Путь к конфигу рынков по умолчанию, если `MARKETS_CONFIG` не задан.
+const DEFAULT_MARKETS_CONFIG: &str = "markets.toml";
+/// Точность нативных ассетов Fuel, используется если в конфиге не указано иное.
+const DEFAULT_DECIMALS: u32 = 9;
+/// Больше не помещается в `pricescale` и `format_units`.
+const MAX_DECIMALS: u32 = 18;
+
+fn default_decimals() -> u32 {
+    DEFAULT_DECIMALS
+}
+
+/// Символ и точности необязательны: если рынок читается из контракта,
+/// они берутся оттуда.
+#[derive(Debug, Clone, Deserialize)]
+struct MarketEntry {
+    id: String,
+    symbol: Option<String>,
+    #[serde(default = "default_decimals")]
+    base_decimals: u32,
+    #[serde(default = "default_decimals")]
+    quote_decimals: u32,
+    #[serde(default = "default_decimals")]
+    price_decimals: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct MarketsFile {
+    markets: Vec<MarketEntry>,
+    /// Тикеры ассетов по id, для имен рынков без явного `symbol`.
+    #[serde(default)]
+    assets: HashMap<String, String>,
+}
+
+/// Spark рынок, который индексируется процессом.
+#[derive(Debug, Clone)]
+pub struct Market {
+    pub id: H256,
+    pub symbol: String,
+    /// Точность базового ассета, в ней приходят объемы ордеров и сделок.
+    pub base_decimals: u32,
+    pub quote_decimals: u32,
+    /// Точность цены в событиях контракта.
+    pub price_decimals: u32,
+    /// Метаданные из контракта, если их удалось получить.
+    pub metadata: Option<MarketMetadata>,
+    /// Символ задан в конфиге или под ним уже индексировались данные,
+    /// метаданные его не меняют.
+    symbol_fixed: bool,
+}
+
+impl Market {
+    /// Цена в единицах котируемого ассета.
+    pub fn price(&self, raw: u128) -> f64 {
+        raw as f64 / 10f64.powi(self.price_decimals as i32)
+    }
+
+    /// Объем в единицах базового ассета.
+    pub fn base_amount(&self, raw: u128) -> f64 {
+        raw as f64 / 10f64.powi(self.base_decimals as i32)
+    }
+
+    /// `pricescale` для TradingView: 10 в степени числа знаков цены.
+    pub fn pricescale(&self) -> u64 {
+        10u64.pow(self.price_decimals)
+    }
+}
+
+/// Форматирует целое значение с `decimals` знаками после запятой без потери точности.
+pub fn format_units(raw: u128, decimals: u32) -> String {
+    if decimals == 0 {
+        return raw.to_string();
+    }
+    let divisor = 10u128.pow(decimals);
+    let fraction = format!("{:0width$}", raw % divisor, width = decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        (raw / divisor).to_string()
+    } else {
+        format!("{}.{}", raw / divisor, fraction)
+    }
+}
+
+fn check_decimals(symbol: &str, base: u32, quote: u32, price: u32) -> Result<(), Error> {
+    if base > MAX_DECIMALS || quote > MAX_DECIMALS || price > MAX_DECIMALS {
+        return Err(Error::ConfigError(format!(
+            "Market {}: decimals must not exceed {}",
+            symbol, MAX_DECIMALS
+        )));
+    }
+    Ok(())
+}
+
+/// Список сконфигурированных рынков.
+#[derive(Debug, Clone)]
+pub struct Markets {
+    markets: Vec<Market>,
+    asset_names: HashMap<String, String>,
+}
+
+impl Markets {
+    /// Загружает рынки из TOML файла, путь берется из `MARKETS_CONFIG`.
+    pub fn load() -> Result<Self, Error> {
+        let path = ev("MARKETS_CONFIG").unwrap_or_else(|_| DEFAULT_MARKETS_CONFIG.to_string());
+        let content = fs::read_to_string(&path)
+            .map_err(|e| Error::ConfigError(format!("Failed to read {}: {}", path, e)))?;
+        Self::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, Error> {
+        let file: MarketsFile = toml::from_str(content)?;
+        if file.markets.is_empty() {
+            return Err(Error::ConfigError("No markets configured".to_string()));
+        }
+
+        let mut markets = Vec::with_capacity(file.markets.len());
+        for entry in file.markets {
+            let id = H256::from_str(&entry.id)?;
+            let symbol_fixed = entry.symbol.is_some();
+            // Временное имя по id, пока не пришли метаданные из контракта
+            let symbol = entry.symbol.unwrap_or_else(|| format!("{:?}", id)[..10].to_string());
+            if markets.iter().any(|m: &Market| m.id == id || m.symbol == symbol) {
+                return Err(Error::ConfigError(format!(
+                    "Duplicate market {} ({})",
+                    symbol, entry.id
+                )));
+            }
+            check_decimals(&symbol, entry.base_decimals, entry.quote_decimals, entry.price_decimals)?;
+            markets.push(Market {
+                id,
+                symbol,
+                base_decimals: entry.base_decimals,
+                quote_decimals: entry.quote_decimals,
+                price_decimals: entry.price_decimals,
+                metadata: None,
+                symbol_fixed,
+            });
+        }
+
+        let asset_names = file
+            .assets
+            .into_iter()
+            .map(|(id, name)| (id.to_lowercase(), name))
+            .collect();
+        Ok(Self {
+            markets,
+            asset_names,
+        })
+    }
+
+    /// Применяет метаданные контракта: точности из контракта важнее конфига,
+    /// рынок без закрепленного символа получает имя по тикерам своих ассетов.
+    /// Метаданные с недопустимыми точностями отклоняются, рынок остается как был.
+    pub fn apply_metadata(&mut self, id: H256, metadata: MarketMetadata) -> Result<(), Error> {
+        let name = format!(
+            "{}/{}",
+            self.asset_name(&metadata.base_asset),
+            self.asset_name(&metadata.quote_asset)
+        );
+        let name_taken = self.markets.iter().any(|m| m.id != id && m.symbol == name);
+        let Some(market) = self.markets.iter_mut().find(|m| m.id == id) else {
+            return Ok(());
+        };
+        check_decimals(
+            &market.symbol,
+            metadata.base_decimals,
+            metadata.quote_decimals,
+            metadata.price_decimals,
+        )?;
+
+        market.base_decimals = metadata.base_decimals;
+        market.quote_decimals = metadata.quote_decimals;
+        market.price_decimals = metadata.price_decimals;
+        if !market.symbol_fixed && !name_taken {
+            market.symbol = name;
+        }
+        market.metadata = Some(metadata);
+        Ok(())
+    }
+
+    /// Закрепляет символ, под которым рынок уже индексировался: данные хранятся
+    /// по символу, и переименование оставило бы их без рынка. Символ из конфига
+    /// важнее.
+    pub fn pin_symbol(&mut self, id: H256, symbol: &str) {
+        let taken = self.markets.iter().any(|m| m.id != id && m.symbol == symbol);
+        let Some(market) = self.markets.iter_mut().find(|m| m.id == id) else {
+            return;
+        };
+        if market.symbol_fixed {
+            return;
+        }
+        if taken {
+            warn!("Symbol {} of market {:?} is taken by another market", symbol, id);
+            return;
+        }
+        market.symbol = symbol.to_string();
+        market.symbol_fixed = true;
+    }
+
+    /// Тикер ассета из `[assets]`, иначе сокращенный id.
+    fn asset_name(&self, asset_id: &str) -> String {
+        self.asset_names
+            .get(&asset_id.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| asset_id.chars().take(10).collect())
+    }
+
+    /// Ищет рынок по `market_id` из события индексатора.
+    pub fn by_id(&self, market_id: &str) -> Option<&Market> {
+        let id = H256::from_str(market_id).ok()?;
+        self.markets.iter().find(|m| m.id == id)
+    }
+
+    pub fn by_symbol(&self, symbol: &str) -> Option<&Market> {
+        self.markets.iter().find(|m| m.symbol == symbol)
+    }
+
+    pub fn ids(&self) -> HashSet<H256> {
+        self.markets.iter().map(|m| m.id).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Market> {
+        self.markets.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_units_keeps_every_digit() {
+        assert_eq!(format_units(0, 9), "0");
+        assert_eq!(format_units(0, 0), "0");
+        assert_eq!(format_units(5, 9), "0.000000005");
+        assert_eq!(format_units(1_500_000_000, 9), "1.5");
+        assert_eq!(format_units(2_000_000_000, 9), "2");
+        assert_eq!(format_units(1_000_000_000_000_000_001, 18), "1.000000000000000001");
+        assert_eq!(format_units(u128::MAX, 0), "340282366920938463463374607431768211455");
+        assert_eq!(
+            format_units(u128::MAX, MAX_DECIMALS),
+            "340282366920938463463.374607431768211455"
+        );
+    }
+}
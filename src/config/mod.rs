@@ -1 +1,4 @@
+pub mod chain;
 pub mod env;
+pub mod market_metadata;
+pub mod markets;
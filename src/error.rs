@@ -18,6 +18,9 @@ pub enum Error {
     #[error("Unknown order type: {0}")]
     UnknownOrderType(String),
 
+    #[error("Unknown order status: {0}")]
+    UnknownOrderStatus(String),
+
     #[error("Anyhow error: {0}")]
     AnyhowError(#[from] anyhow::Error),
 
@@ -36,10 +39,34 @@ pub enum Error {
     #[error("Parsing error: {0}")]
     ParsingError(#[from] ParsingError),
 
-    #[error("Unknown chain id")]
-    UnknownChainIdError,
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
+    #[error("Sled error {0}")]
+    SledError(#[from] sled::Error),
+
+    #[error("Toml error {0}")]
+    TomlError(#[from] toml::de::Error),
+
+    #[error("Block {0} not found or has no timestamp")]
+    BlockNotFound(i64),
+
+    #[error("Unknown event type: {0}")]
+    UnknownEventType(String),
+
+    #[error("Indexer event channel closed")]
+    SinkClosed,
+
+    #[error("Source disconnected: {0}")]
+    SourceDisconnected(String),
+
+    #[error("Indexer {0} does not support reprocessing raw payloads")]
+    ReprocessNotSupported(String),
+
+    #[error("Unknown CHAIN '{0}', expected one of: FUEL, FUELTESTNET, DEVNET")]
+    UnknownChain(String),
 
-    #[error("Pangea ws max retries exceeded")]
+    #[error("Max retries exceeded")]
     MaxRetriesExceeded
 }
 
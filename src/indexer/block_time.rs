@@ -0,0 +1,114 @@
+use fuels::accounts::provider::Provider;
+use fuels::types::BlockHeight;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use log::debug;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::config::env::ev;
+use crate::error::Error;
+
+/// Размер кэша времени блоков по умолчанию.
+const DEFAULT_BLOCK_TIME_CACHE_SIZE: usize = 10_000;
+
+/// Ограниченный кэш высота блока -> unix timestamp.
+/// При переполнении вытесняются самые старые записи.
+struct BlockTimeCache {
+    capacity: usize,
+    times: HashMap<i64, i64>,
+    order: VecDeque<i64>,
+}
+
+impl BlockTimeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            times: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, height: i64) -> Option<i64> {
+        self.times.get(&height).copied()
+    }
+
+    fn insert(&mut self, height: i64, timestamp: i64) {
+        if self.times.insert(height, timestamp).is_some() {
+            return;
+        }
+        self.order.push_back(height);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.times.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Получает реальное время блоков через Fuel ноду.
+pub struct BlockTimeResolver {
+    provider: Provider,
+    cache: Mutex<BlockTimeCache>,
+}
+
+impl BlockTimeResolver {
+    pub fn new(provider: Provider, cache_size: usize) -> Self {
+        Self {
+            provider,
+            cache: Mutex::new(BlockTimeCache::new(cache_size)),
+        }
+    }
+
+    /// Размер кэша берется из `BLOCK_TIME_CACHE_SIZE`.
+    pub fn from_env(provider: Provider) -> Self {
+        let cache_size = ev("BLOCK_TIME_CACHE_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_BLOCK_TIME_CACHE_SIZE);
+        Self::new(provider, cache_size)
+    }
+
+    /// Возвращает unix timestamp блока, сначала проверяя кэш.
+    pub async fn timestamp(&self, block_number: i64) -> Result<i64, Error> {
+        if let Some(timestamp) = self.cache.lock().unwrap().get(block_number) {
+            return Ok(timestamp);
+        }
+
+        let height = u32::try_from(block_number).map_err(|_| Error::BlockNotFound(block_number))?;
+        let block = self
+            .provider
+            .block_by_height(BlockHeight::new(height))
+            .await?
+            .ok_or(Error::BlockNotFound(block_number))?;
+        let timestamp = block
+            .header
+            .time
+            .ok_or(Error::BlockNotFound(block_number))?
+            .timestamp();
+
+        debug!("Resolved block {} time: {}", block_number, timestamp);
+        self.cache.lock().unwrap().insert(block_number, timestamp);
+        Ok(timestamp)
+    }
+
+    /// Загружает в кэш время всех еще неизвестных блоков, до `concurrency`
+    /// запросов одновременно.
+    pub async fn prefetch(
+        &self,
+        heights: impl IntoIterator<Item = i64>,
+        concurrency: usize,
+    ) -> Result<(), Error> {
+        let missing: BTreeSet<i64> = {
+            let cache = self.cache.lock().unwrap();
+            heights
+                .into_iter()
+                .filter(|height| cache.get(*height).is_none())
+                .collect()
+        };
+        stream::iter(missing)
+            .map(|height| self.timestamp(height))
+            .buffer_unordered(concurrency.max(1))
+            .try_for_each(|_| async { Ok(()) })
+            .await
+    }
+}
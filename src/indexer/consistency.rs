@@ -0,0 +1,204 @@
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::interval;
+
+use crate::config::env::ev;
+use crate::config::markets::Markets;
+use crate::indexer::source::Indexer;
+use crate::indexer::spot_order::SpotOrder;
+use crate::storage::order_book::IndexerOrderBooks;
+
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct OrderMismatch {
+    pub order_id: String,
+    pub field: String,
+    pub left: String,
+    pub right: String,
+}
+
+/// Расхождения книг одного рынка у двух индексаторов.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct MarketDiff {
+    pub market: String,
+    pub left: Indexer,
+    pub right: Indexer,
+    pub left_orders: usize,
+    pub right_orders: usize,
+    /// Ордера, которые есть только у `left`.
+    pub only_left: Vec<String>,
+    /// Ордера, которые есть только у `right`.
+    pub only_right: Vec<String>,
+    pub mismatched: Vec<OrderMismatch>,
+}
+
+impl MarketDiff {
+    pub fn is_consistent(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ConsistencyReport {
+    pub checked_at: i64,
+    pub diffs: Vec<MarketDiff>,
+}
+
+/// Периодически сравнивает книги ордеров всех включенных индексаторов.
+pub struct ConsistencyChecker {
+    order_books: Arc<IndexerOrderBooks>,
+    markets: Arc<Markets>,
+    latest: RwLock<Option<ConsistencyReport>>,
+}
+
+pub fn initialize_consistency_checker(
+    tasks: &mut Vec<tokio::task::JoinHandle<()>>,
+    order_books: Arc<IndexerOrderBooks>,
+    markets: Arc<Markets>,
+) -> Arc<ConsistencyChecker> {
+    let checker = Arc::new(ConsistencyChecker::new(order_books, markets));
+
+    // Сравнивать есть что только при нескольких индексаторах
+    if checker.order_books.indexers().len() > 1 {
+        let period = ev("CONSISTENCY_CHECK_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CHECK_INTERVAL_SECS);
+        let task_checker = Arc::clone(&checker);
+        tasks.push(tokio::spawn(async move {
+            task_checker.run(Duration::from_secs(period)).await;
+        }));
+    }
+    checker
+}
+
+impl ConsistencyChecker {
+    pub fn new(order_books: Arc<IndexerOrderBooks>, markets: Arc<Markets>) -> Self {
+        Self {
+            order_books,
+            markets,
+            latest: RwLock::new(None),
+        }
+    }
+
+    pub fn latest(&self) -> Option<ConsistencyReport> {
+        self.latest.read().unwrap().clone()
+    }
+
+    async fn run(&self, period: Duration) {
+        let mut timer = interval(period);
+        loop {
+            timer.tick().await;
+            let report = self.check();
+            *self.latest.write().unwrap() = Some(report);
+        }
+    }
+
+    /// Снимает книги всех индексаторов и сравнивает каждую пару.
+    pub fn check(&self) -> ConsistencyReport {
+        let mut indexers = self.order_books.indexers();
+        indexers.sort_by_key(|i| i.as_str());
+
+        let mut diffs = Vec::new();
+        for market in self.markets.iter() {
+            let snapshots: Vec<(Indexer, HashMap<String, SpotOrder>)> = indexers
+                .iter()
+                .map(|&indexer| {
+                    let orders = self
+                        .order_books
+                        .get(Some(indexer))
+                        .and_then(|books| books.get(&market.symbol))
+                        .map(|book| book.snapshot())
+                        .unwrap_or_default();
+                    (indexer, orders.into_iter().map(|o| (o.id.clone(), o)).collect())
+                })
+                .collect();
+
+            for (i, (left, left_orders)) in snapshots.iter().enumerate() {
+                for (right, right_orders) in snapshots.iter().skip(i + 1) {
+                    let diff = diff_books(&market.symbol, *left, left_orders, *right, right_orders);
+                    if diff.is_consistent() {
+                        info!(
+                            "Consistency {}: {:?} and {:?} agree on {} orders",
+                            diff.market, left, right, diff.left_orders
+                        );
+                    } else {
+                        warn!(
+                            "Consistency {}: {:?} ({} orders) vs {:?} ({} orders): only in {:?}: {}, only in {:?}: {}, mismatched: {}",
+                            diff.market,
+                            left,
+                            diff.left_orders,
+                            right,
+                            diff.right_orders,
+                            left,
+                            diff.only_left.len(),
+                            right,
+                            diff.only_right.len(),
+                            diff.mismatched.len()
+                        );
+                    }
+                    diffs.push(diff);
+                }
+            }
+        }
+
+        ConsistencyReport {
+            checked_at: chrono::Utc::now().timestamp(),
+            diffs,
+        }
+    }
+}
+
+fn diff_books(
+    market: &str,
+    left: Indexer,
+    left_orders: &HashMap<String, SpotOrder>,
+    right: Indexer,
+    right_orders: &HashMap<String, SpotOrder>,
+) -> MarketDiff {
+    let mut only_left = Vec::new();
+    let mut mismatched = Vec::new();
+
+    for (id, l) in left_orders {
+        let Some(r) = right_orders.get(id) else {
+            only_left.push(id.clone());
+            continue;
+        };
+        let mut compare = |field: &str, a: String, b: String| {
+            if a != b {
+                mismatched.push(OrderMismatch {
+                    order_id: id.clone(),
+                    field: field.to_string(),
+                    left: a,
+                    right: b,
+                });
+            }
+        };
+        compare("amount", l.amount.to_string(), r.amount.to_string());
+        compare("price", l.price.to_string(), r.price.to_string());
+        compare("order_type", format!("{:?}", l.order_type), format!("{:?}", r.order_type));
+        compare("status", format!("{:?}", l.status), format!("{:?}", r.status));
+    }
+
+    let only_right = right_orders
+        .keys()
+        .filter(|id| !left_orders.contains_key(*id))
+        .cloned()
+        .collect();
+
+    MarketDiff {
+        market: market.to_string(),
+        left,
+        right,
+        left_orders: left_orders.len(),
+        right_orders: right_orders.len(),
+        only_left,
+        only_right,
+        mismatched,
+    }
+}
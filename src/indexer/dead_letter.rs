@@ -0,0 +1,237 @@
+use log::{error, info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+use crate::error::Error;
+use crate::indexer::source::{EventSource, Indexer, OrderEvent, SourceEvent};
+
+/// На каком этапе событие не удалось обработать.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadLetterStage {
+    /// Сырой payload источника не разобрался. В `payload` — исходная строка.
+    Parse,
+    /// Событие не применилось к сторам. В `payload` — `OrderEvent` в JSON.
+    Apply,
+}
+
+/// Необработанное событие вместе с ошибкой и позицией в цепи.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeadLetter {
+    pub indexer: Indexer,
+    pub stage: DeadLetterStage,
+    pub block: Option<i64>,
+    pub transaction_hash: Option<String>,
+    pub log_index: Option<u64>,
+    pub error: String,
+    pub payload: String,
+    pub recorded_at: i64,
+}
+
+impl DeadLetter {
+    /// Позицию достает из payload, если это хотя бы валидный JSON.
+    pub fn parse_failure(indexer: Indexer, payload: &str, error: &Error) -> Self {
+        let value: Option<serde_json::Value> = serde_json::from_str(payload).ok();
+        let field = |name: &str| value.as_ref().and_then(|v| v.get(name).cloned());
+        Self {
+            indexer,
+            stage: DeadLetterStage::Parse,
+            block: field("block_number").and_then(|v| v.as_i64()),
+            transaction_hash: field("transaction_hash").and_then(|v| v.as_str().map(str::to_string)),
+            log_index: field("log_index").and_then(|v| v.as_u64()),
+            error: error.to_string(),
+            payload: payload.to_string(),
+            recorded_at: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    pub fn apply_failure(indexer: Indexer, event: &OrderEvent, error: &Error) -> Self {
+        Self {
+            indexer,
+            stage: DeadLetterStage::Apply,
+            block: Some(event.position.height),
+            transaction_hash: Some(event.position.transaction_hash.clone()),
+            log_index: Some(event.position.log_index),
+            error: error.to_string(),
+            payload: serde_json::to_string(event).unwrap_or_default(),
+            recorded_at: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Dead-letter очередь в JSONL файле, общая для всех индексаторов.
+pub struct DeadLetterQueue {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Дописывает запись. Ошибка записи только логируется: обработка потока
+    /// не должна вставать из-за dead-letter файла.
+    pub fn push(&self, letter: DeadLetter) {
+        warn!(
+            "Dead-lettering {:?} event at block {:?}: {}",
+            letter.stage, letter.block, letter.error
+        );
+        let _guard = self.lock.lock().unwrap();
+        if let Err(e) = self.append(&[letter]) {
+            error!("Failed to write dead letter to {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<DeadLetter>, Error> {
+        let _guard = self.lock.lock().unwrap();
+        self.read()
+    }
+
+    /// Заменяет первые `count` записей на `remaining`, записи, добавленные
+    /// после чтения, остаются. Файл подменяется атомарно, так что падение
+    /// посреди записи не потеряет очередь.
+    fn replace_head(&self, count: usize, remaining: &[DeadLetter]) -> Result<(), Error> {
+        let _guard = self.lock.lock().unwrap();
+        let letters = self.read()?;
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        write_letters(&mut file, remaining.iter().chain(letters.iter().skip(count)))?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn read(&self) -> Result<Vec<DeadLetter>, Error> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut letters = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                letters.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(letters)
+    }
+
+    fn append(&self, letters: &[DeadLetter]) -> Result<(), Error> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        write_letters(&mut file, letters)
+    }
+}
+
+fn write_letters<'a>(
+    file: &mut fs::File,
+    letters: impl IntoIterator<Item = &'a DeadLetter>,
+) -> Result<(), Error> {
+    for letter in letters {
+        serde_json::to_writer(&mut *file, letter)?;
+        file.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct ReprocessReport {
+    /// Сколько событий отправлено обратно индексаторам.
+    pub reprocessed: usize,
+    /// Сколько записей снова не разобралось и осталось в очереди.
+    pub failed: usize,
+    pub errors: Vec<String>,
+}
+
+/// Источник и канал его потребителя, куда возвращаются события из очереди.
+struct Route {
+    source: Arc<dyn EventSource>,
+    sink: mpsc::Sender<SourceEvent>,
+}
+
+/// Повторно прогоняет dead-letter записи через индексаторы, например после
+/// исправления парсера.
+pub struct DeadLetterReprocessor {
+    queue: Arc<DeadLetterQueue>,
+    routes: HashMap<Indexer, Route>,
+    /// Два прогона одновременно отправили бы одни и те же записи дважды.
+    running: tokio::sync::Mutex<()>,
+}
+
+impl DeadLetterReprocessor {
+    pub fn new(queue: Arc<DeadLetterQueue>) -> Self {
+        Self {
+            queue,
+            routes: HashMap::new(),
+            running: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn register(&mut self, source: Arc<dyn EventSource>, sink: mpsc::Sender<SourceEvent>) {
+        self.routes.insert(source.indexer(), Route { source, sink });
+    }
+
+    pub fn list(&self) -> Result<Vec<DeadLetter>, Error> {
+        self.queue.list()
+    }
+
+    /// Разбирает записи заново и отдает их потребителям. Из очереди записи
+    /// убираются только после доставки, то, что снова не разобралось, остается
+    /// с новой ошибкой. Ошибки применения потребитель запишет в очередь сам.
+    pub async fn reprocess(&self) -> Result<ReprocessReport, Error> {
+        let _running = self.running.lock().await;
+        let letters = self.queue.list()?;
+        let count = letters.len();
+        let mut report = ReprocessReport::default();
+        let mut remaining = Vec::new();
+
+        for mut letter in letters {
+            match self.redeliver(&letter).await {
+                Ok(()) => report.reprocessed += 1,
+                Err(e) => {
+                    report.failed += 1;
+                    report.errors.push(format!(
+                        "{} at block {:?}: {}",
+                        letter.indexer.as_str(),
+                        letter.block,
+                        e
+                    ));
+                    letter.error = e.to_string();
+                    remaining.push(letter);
+                }
+            }
+        }
+
+        self.queue.replace_head(count, &remaining)?;
+        info!(
+            "Dead letters reprocessed: {}, still failing: {}",
+            report.reprocessed, report.failed
+        );
+        Ok(report)
+    }
+
+    async fn redeliver(&self, letter: &DeadLetter) -> Result<(), Error> {
+        let route = self.routes.get(&letter.indexer).ok_or_else(|| {
+            Error::ConfigError(format!("Indexer {} is not running", letter.indexer.as_str()))
+        })?;
+        let event = match letter.stage {
+            DeadLetterStage::Parse => route.source.decode_payload(&letter.payload).await?,
+            DeadLetterStage::Apply => serde_json::from_str(&letter.payload)?,
+        };
+        route
+            .sink
+            .send(SourceEvent::Redelivered(event))
+            .await
+            .map_err(|_| Error::SinkClosed)
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+
+/// Идентификатор события в цепи.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EventKey {
+    pub transaction_hash: String,
+    pub log_index: u64,
+}
+
+/// Множество уже примененных событий с ограниченным размером.
+/// При переполнении забываются самые старые ключи.
+pub struct EventDeduplicator {
+    capacity: usize,
+    seen: HashSet<EventKey>,
+    order: VecDeque<EventKey>,
+}
+
+impl EventDeduplicator {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn contains(&self, key: &EventKey) -> bool {
+        self.seen.contains(key)
+    }
+
+    /// Отмечает событие примененным. Возвращает `false`, если оно уже было.
+    pub fn insert(&mut self, key: EventKey) -> bool {
+        if !self.seen.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Забывает событие, например после отката блока при реорганизации.
+    pub fn remove(&mut self, key: &EventKey) {
+        if self.seen.remove(key) {
+            self.order.retain(|k| k != key);
+        }
+    }
+
+    /// Ключи в порядке применения, для сохранения в чекпоинт.
+    pub fn snapshot(&self) -> Vec<EventKey> {
+        self.order.iter().cloned().collect()
+    }
+
+    pub fn restore(&mut self, keys: Vec<EventKey>) {
+        self.seen.clear();
+        self.order.clear();
+        for key in keys {
+            self.insert(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(log_index: u64) -> EventKey {
+        EventKey {
+            transaction_hash: "0xabc".to_string(),
+            log_index,
+        }
+    }
+
+    #[test]
+    fn applied_key_is_skipped_after_restore() {
+        let mut applied = EventDeduplicator::new(10);
+        assert!(applied.insert(key(1)));
+        assert!(applied.insert(key(2)));
+        assert!(!applied.insert(key(1)));
+
+        let mut restored = EventDeduplicator::new(10);
+        restored.restore(applied.snapshot());
+        assert!(restored.contains(&key(1)));
+        assert!(!restored.insert(key(2)));
+        assert!(restored.insert(key(3)));
+    }
+
+    #[test]
+    fn window_forgets_oldest_keys_first() {
+        let mut applied = EventDeduplicator::new(3);
+        for log_index in 0..5 {
+            assert!(applied.insert(key(log_index)));
+        }
+        assert_eq!(applied.snapshot(), [key(2), key(3), key(4)]);
+        assert!(!applied.contains(&key(1)));
+
+        // Восстановление в окно меньше сохраненного оставляет самые новые
+        let mut restored = EventDeduplicator::new(2);
+        restored.restore(applied.snapshot());
+        assert_eq!(restored.snapshot(), [key(3), key(4)]);
+        assert!(restored.insert(key(2)));
+    }
+
+    #[test]
+    fn removed_key_can_be_applied_again() {
+        let mut applied = EventDeduplicator::new(3);
+        applied.insert(key(1));
+        applied.insert(key(2));
+        applied.remove(&key(1));
+        assert_eq!(applied.snapshot(), [key(2)]);
+        assert!(applied.insert(key(1)));
+    }
+}
@@ -0,0 +1,46 @@
+use crate::error::Error;
+use crate::indexer::graphql_ws::{GraphqlSnapshotSource, Protocol, SnapshotBackend};
+use crate::indexer::source::Indexer;
+use crate::indexer::spot_order::{OrderPayloadEnvio, OrderType, SpotOrder, SpotOrderEnvio};
+
+const ORDER_FIELDS: &str = "id user asset amount price timestamp order_type status asset_type db_write_timestamp initial_amount";
+
+/// Envio (Hasura, протокол `graphql-ws`), снимки `ActiveBuyOrder`/`ActiveSellOrder`.
+pub struct Envio;
+
+pub type EnvioSource = GraphqlSnapshotSource<Envio>;
+
+impl SnapshotBackend for Envio {
+    type Order = SpotOrderEnvio;
+    type Data = OrderPayloadEnvio;
+
+    const INDEXER: Indexer = Indexer::Envio;
+    const NAME: &'static str = "Envio";
+    const PROTOCOL: Protocol = Protocol::GraphqlWs;
+
+    fn subscription(market_id: &str, order_type: OrderType) -> String {
+        let table = match order_type {
+            OrderType::Buy => "ActiveBuyOrder",
+            OrderType::Sell => "ActiveSellOrder",
+        };
+        format!(
+            "subscription {{ {}(where: {{market: {{_eq: \"{}\"}}}}) {{ {} }} }}",
+            table, market_id, ORDER_FIELDS
+        )
+    }
+
+    fn orders(data: OrderPayloadEnvio, order_type: OrderType) -> Option<Vec<SpotOrderEnvio>> {
+        match order_type {
+            OrderType::Buy => data.active_buy_order,
+            OrderType::Sell => data.active_sell_order,
+        }
+    }
+
+    fn order_id(order: &SpotOrderEnvio) -> &str {
+        &order.id
+    }
+
+    fn convert(order: SpotOrderEnvio) -> Result<SpotOrder, Error> {
+        SpotOrder::from_indexer_envio(order)
+    }
+}
@@ -0,0 +1,343 @@
+use async_trait::async_trait;
+use fuels::accounts::provider::Provider;
+use fuels::accounts::wallet::WalletUnlocked;
+use fuels::client::{PageDirection, PaginationRequest};
+use fuels::core::codec::LogDecoder;
+use fuels::tx::Receipt;
+use fuels::types::block::Block;
+use fuels::types::tx_status::TxStatus;
+use fuels::types::{Bits256, ContractId, Identity};
+use log::{debug, info};
+use spark_market_sdk::{
+    CancelOrderEvent, Market as SparkMarket, OpenOrderEvent, OrderType as SparkOrderType,
+    TradeOrderEvent,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+use crate::config::chain::Chain;
+use crate::config::env::ev;
+use crate::config::markets::{Market, Markets};
+use crate::error::Error;
+use crate::indexer::source::{
+    BlockPosition, EventSource, Indexer, OrderEvent, OrderEventKind, SourceEvent,
+};
+use crate::indexer::spot_order::OrderType;
+
+/// Сколько блоков запрашивать у ноды за раз.
+const DEFAULT_BATCH_SIZE: i32 = 100;
+/// Как часто опрашивать ноду о новых блоках после догонки.
+const DEFAULT_POLL_INTERVAL_MS: u64 = 1_000;
+
+/// Декодер логов одного Spark рынка.
+struct MarketLogs {
+    market_id: String,
+    contract_id: ContractId,
+    decoder: LogDecoder,
+}
+
+/// Источник событий напрямую из Fuel ноды, без Pangea: читает блоки,
+/// квитанции транзакций и декодирует логи контрактов рынков по ABI из `spark-market-sdk`.
+pub struct FuelNodeSource {
+    provider: Provider,
+    markets: Vec<MarketLogs>,
+    contract_start_block: i64,
+    batch_size: i32,
+    poll_interval: Duration,
+}
+
+impl FuelNodeSource {
+    pub async fn from_env(markets: Arc<Markets>, chain: Chain) -> Result<Self, Error> {
+        let provider = chain.connect().await?;
+        let contract_start_block: i64 = ev("CONTRACT_START_BLOCK")?.parse()?;
+        let batch_size = ev("FUEL_NODE_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_BATCH_SIZE);
+        let poll_interval = ev("FUEL_NODE_POLL_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_POLL_INTERVAL_MS);
+
+        // Кошелек нужен только чтобы собрать инстанс контракта ради его декодера логов,
+        // транзакции им не подписываются.
+        let wallet = WalletUnlocked::new_random(Some(provider.clone()));
+        let markets = markets
+            .iter()
+            .map(|market| MarketLogs::new(market, &wallet))
+            .collect();
+
+        Ok(Self {
+            provider,
+            markets,
+            contract_start_block,
+            batch_size,
+            poll_interval: Duration::from_millis(poll_interval),
+        })
+    }
+
+    /// Отдает события блоков после `last_block` до последнего блока ноды.
+    /// Возвращает высоту последнего обработанного блока.
+    async fn sync_to_head(&self, sink: &mpsc::Sender<SourceEvent>, mut last_block: i64) -> Result<i64, Error> {
+        let head = self.provider.latest_block_height().await? as i64;
+        while last_block < head {
+            let request = PaginationRequest {
+                cursor: (last_block >= 0).then(|| last_block.to_string()),
+                results: self.batch_size,
+                direction: PageDirection::Forward,
+            };
+            let page = self.provider.get_blocks(request).await?;
+            if page.results.is_empty() {
+                break;
+            }
+            for block in page.results {
+                let height = block.header.height as i64;
+                self.process_block(sink, &block).await?;
+                send(sink, SourceEvent::BlockCompleted(height)).await?;
+                last_block = height;
+            }
+            debug!("Fuel node synced up to block {}", last_block);
+        }
+        Ok(last_block)
+    }
+
+    async fn process_block(&self, sink: &mpsc::Sender<SourceEvent>, block: &Block) -> Result<(), Error> {
+        let height = block.header.height as i64;
+        let timestamp = block
+            .header
+            .time
+            .ok_or(Error::BlockNotFound(height))?
+            .timestamp();
+        let block_hash = format!("0x{}", hex::encode(block.id));
+
+        for (transaction_index, tx_id) in block.transactions.iter().enumerate() {
+            let receipts = match self.provider.tx_status(tx_id).await? {
+                TxStatus::Success { receipts, .. } => receipts,
+                _ => continue,
+            };
+            let position = |log_index: u64| BlockPosition {
+                height,
+                hash: block_hash.clone(),
+                transaction_hash: format!("0x{}", hex::encode(tx_id)),
+                transaction_index: transaction_index as u64,
+                log_index,
+            };
+
+            for (receipt_index, receipt) in receipts.iter().enumerate() {
+                let Receipt::LogData { id, .. } = receipt else {
+                    continue;
+                };
+                let Some(market) = self.markets.iter().find(|m| m.contract_id == *id) else {
+                    continue;
+                };
+                for event in market.decode(receipt, receipt_index as u64, &position, timestamp) {
+                    send(sink, SourceEvent::Order(event)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MarketLogs {
+    fn new(market: &Market, wallet: &WalletUnlocked) -> Self {
+        let contract_id = ContractId::new(market.id.0);
+        Self {
+            market_id: format!("{:?}", market.id),
+            contract_id,
+            decoder: SparkMarket::new(contract_id, wallet.clone()).log_decoder(),
+        }
+    }
+
+    /// Декодирует один `LogData` рынка в нормализованные события.
+    /// Сделка дает два события, по одному на каждый ордер; чтобы у них были
+    /// разные ключи дедупликации, `log_index` = индекс квитанции * 2 + сторона.
+    /// В свечи идет только событие продающей стороны.
+    fn decode(
+        &self,
+        receipt: &Receipt,
+        receipt_index: u64,
+        position: &dyn Fn(u64) -> BlockPosition,
+        timestamp: i64,
+    ) -> Vec<OrderEvent> {
+        let receipts = std::slice::from_ref(receipt);
+        let log_index = receipt_index * 2;
+        let event = |log_index, kind, order_id: &Bits256| OrderEvent {
+            position: position(log_index),
+            timestamp,
+            market_id: self.market_id.clone(),
+            order_id: bits_to_string(order_id),
+            kind,
+            order_type: None,
+            asset: None,
+            amount: None,
+            price: None,
+            user: None,
+            counterpart: false,
+        };
+
+        if let Ok(mut logs) = self.decoder.decode_logs_with_type::<OpenOrderEvent>(receipts) {
+            if let Some(log) = logs.pop() {
+                return vec![OrderEvent {
+                    order_type: Some(order_type(&log.order_type)),
+                    asset: Some(format!("0x{}", hex::encode(log.asset))),
+                    amount: Some(log.amount as u128),
+                    price: Some(log.price as u128),
+                    user: Some(identity_to_string(&log.user)),
+                    ..event(log_index, OrderEventKind::Open, &log.order_id)
+                }];
+            }
+        }
+        if let Ok(mut logs) = self.decoder.decode_logs_with_type::<CancelOrderEvent>(receipts) {
+            if let Some(log) = logs.pop() {
+                return vec![OrderEvent {
+                    user: Some(identity_to_string(&log.user)),
+                    ..event(log_index, OrderEventKind::Cancel, &log.order_id)
+                }];
+            }
+        }
+        if let Ok(mut logs) = self.decoder.decode_logs_with_type::<TradeOrderEvent>(receipts) {
+            if let Some(log) = logs.pop() {
+                let trade = |log_index, order_id, order_type, user, counterpart| OrderEvent {
+                    order_type: Some(order_type),
+                    amount: Some(log.trade_size as u128),
+                    price: Some(log.trade_price as u128),
+                    user: Some(user),
+                    counterpart,
+                    ..event(log_index, OrderEventKind::Trade, order_id)
+                };
+                return vec![
+                    trade(
+                        log_index,
+                        &log.base_sell_order_id,
+                        OrderType::Sell,
+                        identity_to_string(&log.seller),
+                        false,
+                    ),
+                    trade(
+                        log_index + 1,
+                        &log.base_buy_order_id,
+                        OrderType::Buy,
+                        identity_to_string(&log.buyer),
+                        true,
+                    ),
+                ];
+            }
+        }
+
+        // Остальные логи рынка (депозиты, выводы и т.п.) к ордерам не относятся
+        debug!(
+            "Skipping non-order log of market {} at receipt {}",
+            self.market_id, receipt_index
+        );
+        Vec::new()
+    }
+}
+
+#[async_trait]
+impl EventSource for FuelNodeSource {
+    fn indexer(&self) -> Indexer {
+        Indexer::FuelNode
+    }
+
+    async fn run(&self, last_block: Option<i64>, sink: mpsc::Sender<SourceEvent>) -> Result<(), Error> {
+        let mut last_block = last_block.unwrap_or(self.contract_start_block - 1);
+        info!("Fuel node source starting after block {}", last_block);
+
+        last_block = self.sync_to_head(&sink, last_block).await?;
+        send(&sink, SourceEvent::CaughtUp).await?;
+        info!("Fuel node source caught up at block {}, polling for new blocks", last_block);
+
+        // Ошибки опроса уходят супервизору, он перезапустит источник с последнего блока
+        loop {
+            sleep(self.poll_interval).await;
+            last_block = self.sync_to_head(&sink, last_block).await?;
+        }
+    }
+}
+
+async fn send(sink: &mpsc::Sender<SourceEvent>, event: SourceEvent) -> Result<(), Error> {
+    sink.send(event).await.map_err(|_| Error::SinkClosed)
+}
+
+fn order_type(order_type: &SparkOrderType) -> OrderType {
+    match order_type {
+        SparkOrderType::Buy => OrderType::Buy,
+        SparkOrderType::Sell => OrderType::Sell,
+    }
+}
+
+fn bits_to_string(bits: &Bits256) -> String {
+    format!("0x{}", hex::encode(bits.0))
+}
+
+fn identity_to_string(identity: &Identity) -> String {
+    match identity {
+        Identity::Address(address) => format!("0x{}", hex::encode(address)),
+        Identity::ContractId(contract_id) => format!("0x{}", hex::encode(contract_id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::order_event_handler::OrderEventHandler;
+    use crate::storage::candles::InMemoryCandleStore;
+    use crate::storage::checkpoint::CheckpointStore;
+    use crate::storage::order_book::OrderBooks;
+    use crate::storage::retention::Retention;
+    use crate::storage::timeframes::{Timeframes, BASE_INTERVAL};
+    use crate::test_node::TestMarket;
+
+    #[tokio::test]
+    async fn trade_receipt_is_counted_in_candles_once() {
+        let market = TestMarket::deploy().await;
+        let trade_size = 2 * 10u64.pow(9);
+        let receipts = market.trade(trade_size, 3_000 * 10u64.pow(9)).await;
+
+        let markets = Arc::new(market.markets(Some("BASE/QUOTE")));
+        let wallet = WalletUnlocked::new_random(Some(market.provider.clone()));
+        let logs = MarketLogs::new(markets.iter().next().unwrap(), &wallet);
+        let position = |log_index| BlockPosition {
+            height: 1,
+            hash: "0x01".to_string(),
+            transaction_hash: "0x02".to_string(),
+            transaction_index: 0,
+            log_index,
+        };
+        let events: Vec<OrderEvent> = receipts
+            .iter()
+            .enumerate()
+            .filter(|(_, receipt)| {
+                matches!(receipt, Receipt::LogData { id, .. } if *id == market.contract_id)
+            })
+            .flat_map(|(index, receipt)| logs.decode(receipt, index as u64, &position, 60))
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.kind == OrderEventKind::Trade));
+
+        let timeframes = Arc::new(Timeframes::from_env(Arc::new(InMemoryCandleStore::new(
+            Retention::default(),
+        ))));
+        let checkpoint =
+            std::env::temp_dir().join(format!("fuel-node-test-{}.json", uuid::Uuid::new_v4()));
+        let handler = OrderEventHandler::new(
+            Some(Arc::clone(&timeframes)),
+            Arc::new(OrderBooks::new()),
+            markets,
+            CheckpointStore::new(checkpoint, Duration::from_secs(3600)),
+            100,
+            1_000,
+        );
+        for event in &events {
+            handler.handle_order_event(event).unwrap();
+        }
+
+        let candles =
+            timeframes.get_candles_in_time_range_secs("BASE/QUOTE", BASE_INTERVAL, 0, 120);
+        let volume: u128 = candles.iter().map(|c| c.volume).sum();
+        assert_eq!(volume, trade_size as u128);
+    }
+}
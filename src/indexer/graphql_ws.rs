@@ -0,0 +1,401 @@
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use tokio::sync::mpsc;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::config::markets::Markets;
+use crate::error::Error;
+use crate::indexer::source::{EventSource, Indexer, SourceEvent};
+use crate::indexer::spot_order::{OrderType, SpotOrder};
+
+/// Вариант протокола GraphQL поверх WebSocket.
+#[derive(Debug, Clone, Copy)]
+pub enum Protocol {
+    /// Старый `graphql-ws` (subscriptions-transport-ws), так работает Hasura.
+    GraphqlWs,
+    /// `graphql-transport-ws` из пакета graphql-ws.
+    GraphqlTransportWs,
+}
+
+impl Protocol {
+    fn name(&self) -> &'static str {
+        match self {
+            Protocol::GraphqlWs => "graphql-ws",
+            Protocol::GraphqlTransportWs => "graphql-transport-ws",
+        }
+    }
+
+    fn subscribe_type(&self) -> &'static str {
+        match self {
+            Protocol::GraphqlWs => "start",
+            Protocol::GraphqlTransportWs => "subscribe",
+        }
+    }
+}
+
+/// Бэкенд, который отдает полные наборы активных ордеров через GraphQL подписки.
+pub trait SnapshotBackend: Send + Sync + 'static {
+    /// Ордер в формате бэкенда.
+    type Order: DeserializeOwned + Send;
+    /// Поле `data` сообщения подписки.
+    type Data: DeserializeOwned + Send;
+
+    const INDEXER: Indexer;
+    const NAME: &'static str;
+    const PROTOCOL: Protocol;
+
+    /// Подписка на активные ордера стороны `order_type` рынка `market_id`.
+    fn subscription(market_id: &str, order_type: OrderType) -> String;
+    fn orders(data: Self::Data, order_type: OrderType) -> Option<Vec<Self::Order>>;
+    fn order_id(order: &Self::Order) -> &str;
+    fn convert(order: Self::Order) -> Result<SpotOrder, Error>;
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlMessage {
+    r#type: String,
+    id: Option<String>,
+    payload: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DataPayload<D> {
+    data: D,
+}
+
+/// Источник снимков: по подписке на каждую сторону каждого сконфигурированного
+/// рынка, запросы фильтруются по id рынка.
+pub struct GraphqlSnapshotSource<B> {
+    url: String,
+    /// id подписки -> рынок и сторона.
+    subscriptions: HashMap<String, (String, OrderType)>,
+    backend: PhantomData<fn() -> B>,
+}
+
+impl<B: SnapshotBackend> GraphqlSnapshotSource<B> {
+    pub fn new(url: impl Into<String>, markets: &Markets) -> Self {
+        let subscriptions = markets
+            .iter()
+            .flat_map(|market| {
+                let market_id = format!("{:?}", market.id);
+                [(OrderType::Buy, "buy"), (OrderType::Sell, "sell")].map(|(order_type, side)| {
+                    (format!("{}:{}", market_id, side), (market_id.clone(), order_type))
+                })
+            })
+            .collect();
+        Self {
+            url: url.into(),
+            subscriptions,
+            backend: PhantomData,
+        }
+    }
+
+    async fn run_session(&self, sink: &mpsc::Sender<SourceEvent>) -> Result<(), Error> {
+        let mut request = self.url.as_str().into_client_request()?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(B::PROTOCOL.name()),
+        );
+
+        let (ws_stream, _) = connect_async(request).await?;
+        let (mut write, mut read) = ws_stream.split();
+        info!("Connected to {} at {}", B::NAME, self.url);
+
+        write
+            .send(Message::Text(json!({ "type": "connection_init", "payload": {} }).to_string()))
+            .await?;
+
+        while let Some(message) = read.next().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                Message::Ping(payload) => {
+                    write.send(Message::Pong(payload)).await?;
+                    continue;
+                }
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            let message: GraphqlMessage = match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Failed to parse {} message: {}: {}", B::NAME, e, text);
+                    continue;
+                }
+            };
+            match message.r#type.as_str() {
+                "connection_ack" => {
+                    info!("{} connection acknowledged", B::NAME);
+                    // graphql-transport-ws разрешает подписки только после ack
+                    for (id, (market_id, order_type)) in &self.subscriptions {
+                        let query = B::subscription(market_id, *order_type);
+                        let subscribe = json!({
+                            "id": id,
+                            "type": B::PROTOCOL.subscribe_type(),
+                            "payload": { "query": query },
+                        });
+                        write.send(Message::Text(subscribe.to_string())).await?;
+                    }
+                }
+                "ping" => {
+                    write
+                        .send(Message::Text(json!({ "type": "pong" }).to_string()))
+                        .await?;
+                }
+                "ka" | "pong" => {}
+                "data" | "next" => self.handle_data(sink, message).await?,
+                "error" | "connection_error" => {
+                    error!("{} returned error: {}", B::NAME, text);
+                }
+                "complete" => {
+                    warn!("{} completed subscription {:?}", B::NAME, message.id);
+                }
+                other => warn!("Unexpected {} message type: {}", B::NAME, other),
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_data(
+        &self,
+        sink: &mpsc::Sender<SourceEvent>,
+        message: GraphqlMessage,
+    ) -> Result<(), Error> {
+        let Some((market_id, order_type)) = message
+            .id
+            .as_ref()
+            .and_then(|id| self.subscriptions.get(id))
+        else {
+            warn!("{} data for unknown subscription {:?}", B::NAME, message.id);
+            return Ok(());
+        };
+        let Some(payload) = message.payload else {
+            return Ok(());
+        };
+        let data = match serde_json::from_value::<DataPayload<B::Data>>(payload) {
+            Ok(payload) => payload.data,
+            Err(e) => {
+                error!("Failed to parse {} orders of {}: {}", B::NAME, market_id, e);
+                return Ok(());
+            }
+        };
+        match B::orders(data, *order_type) {
+            Some(orders) => self.sync_side(sink, market_id, *order_type, orders).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Каждое сообщение подписки несет полный активный набор стороны,
+    /// поэтому ордера, пропавшие из набора, удаляются из книги.
+    async fn sync_side(
+        &self,
+        sink: &mpsc::Sender<SourceEvent>,
+        market_id: &str,
+        order_type: OrderType,
+        orders: Vec<B::Order>,
+    ) -> Result<(), Error> {
+        let orders: Vec<SpotOrder> = orders
+            .into_iter()
+            .filter_map(|order| {
+                let id = B::order_id(&order).to_string();
+                B::convert(order)
+                    .map_err(|e| error!("Failed to convert {} order {}: {}", B::NAME, id, e))
+                    .ok()
+            })
+            .collect();
+
+        sink.send(SourceEvent::Snapshot {
+            market_id: market_id.to_string(),
+            order_type,
+            orders,
+        })
+        .await
+        .map_err(|_| Error::SinkClosed)
+    }
+}
+
+#[async_trait]
+impl<B: SnapshotBackend> EventSource for GraphqlSnapshotSource<B> {
+    fn indexer(&self) -> Indexer {
+        B::INDEXER
+    }
+
+    /// Держит подписку. Обрыв и ошибки сессии уходят супервизору, он
+    /// переподключит источник с паузой. Снимки не привязаны к блокам,
+    /// поэтому `last_block` не используется.
+    async fn run(&self, _last_block: Option<i64>, sink: mpsc::Sender<SourceEvent>) -> Result<(), Error> {
+        self.run_session(&sink).await?;
+        Err(Error::SourceDisconnected(format!(
+            "{} subscription closed by server",
+            B::NAME
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::envio::Envio;
+    use crate::indexer::spot_order::OrderStatus;
+    use crate::indexer::subsquid::Subsquid;
+    use serde_json::Value;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+    use tokio_tungstenite::{accept_hdr_async, WebSocketStream};
+
+    const MARKET_A: &str = "0x0000000000000000000000000000000000000000000000000000000000000001";
+    const MARKET_B: &str = "0x0000000000000000000000000000000000000000000000000000000000000002";
+
+    /// Что увидел сервер и что отдал источник за одну сессию.
+    struct Session {
+        protocol: String,
+        subscribes: Vec<Value>,
+        events: Vec<SourceEvent>,
+        error: Error,
+    }
+
+    fn markets() -> Markets {
+        Markets::from_toml(&format!(
+            "[[markets]]\nid = \"{}\"\nsymbol = \"A/B\"\n\n[[markets]]\nid = \"{}\"\nsymbol = \"C/D\"",
+            MARKET_A, MARKET_B
+        ))
+        .unwrap()
+    }
+
+    async fn read_json(ws: &mut WebSocketStream<TcpStream>) -> Value {
+        loop {
+            if let Message::Text(text) = ws.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Сервер на одно соединение: подтверждает его, собирает `subscriptions`
+    /// подписок, шлет `data` в подписку `reply_to` сообщением `data_type`
+    /// и закрывает соединение.
+    async fn mock_server(
+        listener: TcpListener,
+        subscriptions: usize,
+        data_type: &'static str,
+        reply_to: String,
+        data: Value,
+    ) -> (String, Vec<Value>) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut protocol = String::new();
+        let callback = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            let header = request.headers()["Sec-WebSocket-Protocol"].clone();
+            protocol = header.to_str().unwrap().to_string();
+            response.headers_mut().insert("Sec-WebSocket-Protocol", header);
+            Ok(response)
+        };
+        let mut ws = accept_hdr_async(stream, callback).await.unwrap();
+
+        assert_eq!(read_json(&mut ws).await["type"], "connection_init");
+        ws.send(Message::Text(json!({ "type": "connection_ack" }).to_string()))
+            .await
+            .unwrap();
+        let mut subscribes = Vec::new();
+        while subscribes.len() < subscriptions {
+            subscribes.push(read_json(&mut ws).await);
+        }
+        let message = json!({ "type": data_type, "id": reply_to, "payload": { "data": data } });
+        ws.send(Message::Text(message.to_string())).await.unwrap();
+        ws.close(None).await.unwrap();
+        (protocol, subscribes)
+    }
+
+    async fn run_session<B: SnapshotBackend>(data_type: &'static str, reply_to: String, data: Value) -> Session {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(mock_server(listener, 4, data_type, reply_to, data));
+
+        let source = GraphqlSnapshotSource::<B>::new(url, &markets());
+        let (sink, mut received) = mpsc::channel(16);
+        let error = source.run(None, sink).await.unwrap_err();
+        let (protocol, subscribes) = server.await.unwrap();
+        let mut events = Vec::new();
+        while let Ok(event) = received.try_recv() {
+            events.push(event);
+        }
+        Session {
+            protocol,
+            subscribes,
+            events,
+            error,
+        }
+    }
+
+    /// По подписке на каждую сторону каждого рынка, запрос фильтрует свой рынок.
+    fn assert_subscriptions(session: &Session, subscribe_type: &str, filter: impl Fn(&str) -> String) {
+        let mut ids: Vec<_> = session
+            .subscribes
+            .iter()
+            .map(|subscribe| {
+                assert_eq!(subscribe["type"], subscribe_type);
+                let id = subscribe["id"].as_str().unwrap();
+                let market_id = id.split(':').next().unwrap();
+                let query = subscribe["payload"]["query"].as_str().unwrap();
+                assert!(query.contains(&filter(market_id)), "{} misses its market filter", query);
+                id.to_string()
+            })
+            .collect();
+        ids.sort();
+        let expected = [MARKET_A, MARKET_B]
+            .iter()
+            .flat_map(|market| [format!("{}:buy", market), format!("{}:sell", market)]);
+        assert_eq!(ids, expected.collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn envio_subscribes_per_market_over_graphql_ws() {
+        let data = json!({ "ActiveBuyOrder": [{
+            "id": "order-1", "user": "user", "asset": "asset", "amount": "5", "price": "100",
+            "timestamp": "2024-01-01T00:00:00Z", "order_type": "Buy", "status": "Active",
+            "initial_amount": "10",
+        }] });
+        let session = run_session::<Envio>("data", format!("{}:buy", MARKET_A), data).await;
+
+        assert_eq!(session.protocol, "graphql-ws");
+        assert_subscriptions(&session, "start", |market_id| {
+            format!("where: {{market: {{_eq: \"{}\"}}}}", market_id)
+        });
+        let [SourceEvent::Snapshot { market_id, order_type, orders }] = session.events.as_slice() else {
+            panic!("expected one snapshot, got {:?}", session.events);
+        };
+        assert_eq!((market_id.as_str(), *order_type), (MARKET_A, OrderType::Buy));
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].status, Some(OrderStatus::PartiallyMatched));
+        assert!(matches!(session.error, Error::SourceDisconnected(_)));
+    }
+
+    #[tokio::test]
+    async fn subsquid_subscribes_per_market_over_graphql_transport_ws() {
+        let data = json!({ "activeSellOrders": [{
+            "id": "order-2", "asset": "asset", "amount": "10", "price": "200",
+            "timestamp": "2024-01-01T00:00:00Z", "orderType": "Sell", "user": "user",
+            "status": "Active", "initialAmount": "10",
+        }] });
+        let session = run_session::<Subsquid>("next", format!("{}:sell", MARKET_B), data).await;
+
+        assert_eq!(session.protocol, "graphql-transport-ws");
+        assert_subscriptions(&session, "subscribe", |market_id| {
+            format!("where: {{market_eq: \"{}\"}}", market_id)
+        });
+        let [SourceEvent::Snapshot { market_id, order_type, orders }] = session.events.as_slice() else {
+            panic!("expected one snapshot, got {:?}", session.events);
+        };
+        assert_eq!((market_id.as_str(), *order_type), (MARKET_B, OrderType::Sell));
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].status, Some(OrderStatus::New));
+        assert!(matches!(session.error, Error::SourceDisconnected(_)));
+    }
+}
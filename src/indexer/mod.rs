@@ -1,3 +1,17 @@
+pub mod block_time;
+pub mod consistency;
+pub mod dead_letter;
+pub mod dedup;
+pub mod envio;
+pub mod fuel_node;
+pub mod graphql_ws;
 pub mod order_event_handler;
 pub mod pangea;
+pub mod pipeline;
+pub mod recorder;
+pub mod reorg;
+pub mod replay;
+pub mod source;
 pub mod spot_order;
+pub mod subsquid;
+pub mod supervisor;
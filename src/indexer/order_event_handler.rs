@@ -1,63 +1,411 @@
-use crate::storage::candles::CandleStore;
-use log::{error, info};
-use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct PangeaOrderEvent {
-    pub chain: u64,
-    pub block_number: i64,
-    pub block_hash: String,
-    pub transaction_hash: String,
-    pub transaction_index: u64,
-    pub log_index: u64,
-    pub market_id: String,
-    pub order_id: String,
-    pub event_type: Option<String>,
-    pub asset: Option<String>,
-    pub amount: Option<u128>,
-    pub asset_type: Option<String>,
-    pub order_type: Option<String>,
-    pub price: Option<u128>,
-    pub user: Option<String>,
-    pub order_matcher: Option<String>,
-    pub owner: Option<String>,
-    pub limit_type: Option<String>,
+use crate::config::env::ev;
+use crate::config::markets::Markets;
+use crate::error::Error;
+use crate::indexer::dead_letter::{DeadLetter, DeadLetterQueue};
+use crate::indexer::dedup::EventDeduplicator;
+use crate::indexer::reorg::{AppliedBlock, ReorgJournal, StoreChange};
+use crate::indexer::source::{Indexer, OrderEvent, OrderEventKind, SourceEvent};
+use crate::indexer::supervisor::IndexerStatus;
+use crate::indexer::spot_order::{OrderStatus, OrderType, SpotOrder};
+use crate::storage::checkpoint::{Checkpoint, CheckpointStore, CHECKPOINT_VERSION};
+use crate::storage::order_book::{OrderBook, OrderBooks};
+use crate::storage::timeframes::Timeframes;
+use log::{debug, error, info, warn};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Применяет события ордеров к сторам свечей и книг ордеров.
+pub struct OrderEventHandler {
+    /// Свечи пишет только основной индексатор, иначе сделки посчитаются дважды.
+    candles: Option<Arc<Timeframes>>,
+    order_books: Arc<OrderBooks>,
+    markets: Arc<Markets>,
+    checkpoints: CheckpointStore,
+    journal: Mutex<ReorgJournal>,
+    applied: Mutex<EventDeduplicator>,
+    /// Куда складывать события, которые не удалось применить.
+    dead_letters: Option<(Indexer, Arc<DeadLetterQueue>)>,
+    status: Option<Arc<IndexerStatus>>,
+    /// Куда отправить высоту форка, чтобы источник перечитал блоки с нее.
+    rewinds: Option<mpsc::UnboundedSender<i64>>,
+    /// Откат уже запрошен, события брошенной ветки до `SourceEvent::Rewound` пропускаются.
+    rewinding: Mutex<bool>,
 }
 
-pub async fn handle_order_event(
-    candle_store: Arc<CandleStore>,
-    event: PangeaOrderEvent,
-) {
-    if let Some(event_type) = event.event_type.as_deref() {
-        match event_type {
-            "Trade" => {
-                if let (Some(price), Some(amount)) = (event.price, event.amount) {
-                    let asset = "AAPL"; // Фиксированный символ
-                    let genesis_block = 0; // Блок, соответствующий `genesis_timestamp`
-                    let genesis_timestamp = 1724996333; // Unix timestamp первого блока
+impl OrderEventHandler {
+    pub fn new(
+        candles: Option<Arc<Timeframes>>,
+        order_books: Arc<OrderBooks>,
+        markets: Arc<Markets>,
+        checkpoints: CheckpointStore,
+        reorg_window: usize,
+        dedup_capacity: usize,
+    ) -> Self {
+        Self {
+            candles,
+            order_books,
+            markets,
+            checkpoints,
+            journal: Mutex::new(ReorgJournal::new(reorg_window)),
+            applied: Mutex::new(EventDeduplicator::new(dedup_capacity)),
+            dead_letters: None,
+            status: None,
+            rewinds: None,
+            rewinding: Mutex::new(false),
+        }
+    }
+
+    /// Потребитель отмечает в статусе примененные блоки и переход в реальное время.
+    pub fn with_status(mut self, status: Arc<IndexerStatus>) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// После реорганизации источник перезапускается с высоты форка через `rewinds`.
+    pub fn with_rewinds(mut self, rewinds: mpsc::UnboundedSender<i64>) -> Self {
+        self.rewinds = Some(rewinds);
+        self
+    }
+
+    pub fn with_dead_letters(mut self, indexer: Indexer, queue: Arc<DeadLetterQueue>) -> Self {
+        self.dead_letters = Some((indexer, queue));
+        self
+    }
+
+    /// Восстанавливает сторы из чекпоинта и возвращает последний обработанный блок.
+    pub fn restore_checkpoint(&self) -> Result<Option<i64>, Error> {
+        // Чекпоинт, записанный вместе со свечами, точно им соответствует
+        let committed = match &self.candles {
+            Some(candles) => candles.committed_checkpoint()?,
+            None => None,
+        };
+        let checkpoint = match committed {
+            Some(content) => CheckpointStore::parse(&content, "candle store")?,
+            None => self.checkpoints.load()?,
+        };
+        let Some(checkpoint) = checkpoint else {
+            // История пойдет с начала, оставшиеся на диске свечи посчитались бы дважды.
+            // Стирать их можно только по явному `CANDLE_STORE_RESET=true`
+            let stored = self
+                .candles
+                .as_ref()
+                .filter(|candles| candles.get_min_max_timestamps().is_some());
+            if let Some(candles) = stored {
+                if ev("CANDLE_STORE_RESET").map_or(true, |v| v != "true") {
+                    return Err(Error::ConfigError(
+                        "Candle store has data but no matching checkpoint; \
+                         set CANDLE_STORE_RESET=true to rebuild it from scratch"
+                            .to_string(),
+                    ));
+                }
+                warn!("No checkpoint for stored candles, clearing the candle store");
+                candles.clear()?;
+            }
+            return Ok(None);
+        };
+        if let Some(candles) = &self.candles {
+            candles.restore(checkpoint.candles, checkpoint.trades)?;
+            candles.rebuild_daily(self.markets.iter().map(|m| m.symbol.as_str()))?;
+        }
+        self.order_books.restore(checkpoint.order_books);
+        self.applied.lock().unwrap().restore(checkpoint.applied_events);
+        Ok(Some(checkpoint.last_block))
+    }
+
+    /// Сохраняет чекпоинт, если с прошлого сохранения прошел интервал.
+    /// `last_block` должен быть полностью применен к сторам.
+    pub fn save_checkpoint_if_due(&self, last_block: i64) {
+        if !self.checkpoints.is_due() {
+            return;
+        }
+        let (candles, trades) = match &self.candles {
+            Some(timeframes) => match timeframes.snapshot() {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    error!("Failed to snapshot candles at block {}: {}", last_block, e);
+                    return;
+                }
+            },
+            None => Default::default(),
+        };
+        let checkpoint = Checkpoint {
+            version: CHECKPOINT_VERSION,
+            last_block,
+            candles,
+            trades,
+            order_books: self.order_books.snapshot(),
+            applied_events: self.applied.lock().unwrap().snapshot(),
+        };
+        if let Err(e) = self.save_checkpoint(&checkpoint) {
+            error!("Failed to save checkpoint at block {}: {}", last_block, e);
+        }
+    }
+
+    /// Персистентный стор свечей пишет чекпоинт одной транзакцией со своими
+    /// изменениями: иначе падение между двумя записями оставит свечи впереди
+    /// `last_block`, и после рестарта сделки применятся повторно.
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), Error> {
+        if let Some(candles) = &self.candles {
+            if candles.commit(&serde_json::to_vec(checkpoint)?)? {
+                self.checkpoints.mark_saved();
+                info!("Checkpoint committed with candles at block {}", checkpoint.last_block);
+                return Ok(());
+            }
+        }
+        self.checkpoints.save(checkpoint)
+    }
 
-                    // Вычисляем точное время события
-                    let event_time = genesis_timestamp + (event.block_number - genesis_block) as i64;
+    /// Применяет события из канала источника, пока источник его не закроет.
+    pub async fn consume(&self, mut events: mpsc::Receiver<SourceEvent>) {
+        while let Some(event) = events.recv().await {
+            if *self.rewinding.lock().unwrap()
+                && !matches!(event, SourceEvent::Rewound(_) | SourceEvent::Redelivered(_))
+            {
+                continue;
+            }
+            match event {
+                SourceEvent::Order(event) => {
+                    if let Err(e) = self.handle_order_event(&event) {
+                        self.dead_letter(&event, e);
+                    }
+                }
+                SourceEvent::Redelivered(event) => {
+                    if let Err(e) = self.apply_order_event(&event, false) {
+                        self.dead_letter(&event, e);
+                    }
+                }
+                SourceEvent::Snapshot {
+                    market_id,
+                    order_type,
+                    orders,
+                } => {
+                    self.handle_snapshot(&market_id, order_type, orders);
+                    // Снапшотные бэкенды отдают текущее состояние сразу
+                    if let Some(status) = &self.status {
+                        status.mark_live();
+                    }
+                }
+                SourceEvent::BlockCompleted(height) => {
+                    self.save_checkpoint_if_due(height);
+                    if let Some(status) = &self.status {
+                        status.set_last_block(height);
+                    }
+                }
+                SourceEvent::CaughtUp => {
+                    if let Some(status) = &self.status {
+                        status.mark_live();
+                    }
+                }
+                SourceEvent::Rewound(height) => {
+                    info!("Source rewound to block {}", height);
+                    *self.rewinding.lock().unwrap() = false;
+                }
+            }
+        }
+        warn!("Indexer event stream closed");
+    }
+
+    fn dead_letter(&self, event: &OrderEvent, e: Error) {
+        match &self.dead_letters {
+            Some((indexer, queue)) => queue.push(DeadLetter::apply_failure(*indexer, event, &e)),
+            None => error!("Failed to apply order event: {}", e),
+        }
+    }
 
+    fn handle_snapshot(&self, market_id: &str, order_type: OrderType, orders: Vec<SpotOrder>) {
+        let Some(market) = self.markets.by_id(market_id) else {
+            warn!("Snapshot for unknown market {}, skipping", market_id);
+            return;
+        };
+        let count = orders.len();
+        self.order_books
+            .get_or_create(&market.symbol)
+            .replace_orders(order_type, orders);
+        info!("{} {:?} orders synced: {}", market.symbol, order_type, count);
+    }
+
+    pub fn handle_order_event(&self, event: &OrderEvent) -> Result<(), Error> {
+        self.apply_order_event(event, true)
+    }
+
+    /// `in_order` — событие идет в порядке блоков. Повторно доставленные
+    /// события старых блоков не должны выглядеть как реорганизация.
+    fn apply_order_event(&self, event: &OrderEvent, in_order: bool) -> Result<(), Error> {
+        let Some(market) = self.markets.by_id(&event.market_id) else {
+            warn!("Event for unknown market {}, skipping", event.market_id);
+            return Ok(());
+        };
+        let symbol = market.symbol.as_str();
+
+        if in_order {
+            let orphaned = self
+                .journal
+                .lock()
+                .unwrap()
+                .detect_reorg(event.position.height, &event.position.hash);
+            if !orphaned.is_empty() {
+                self.rollback(orphaned);
+                // Событие придет снова вместе с остальными блоками канонической ветки
+                if *self.rewinding.lock().unwrap() {
+                    return Ok(());
+                }
+            }
+        }
+
+        let key = event.position.key();
+        if self.applied.lock().unwrap().contains(&key) {
+            debug!(
+                "Skipping already applied event {}:{}",
+                key.transaction_hash, key.log_index
+            );
+            return Ok(());
+        }
+
+        let order_book = self.order_books.get_or_create(symbol);
+        let event_time = event.timestamp;
+        let mut changes = vec![StoreChange::Order {
+            symbol: symbol.to_string(),
+            order_id: event.order_id.clone(),
+            previous: order_book.find_order(&event.order_id),
+        }];
+
+        match event.kind {
+            OrderEventKind::Open => handle_open_event(&order_book, event),
+            OrderEventKind::Trade => {
+                if let (Some(price), Some(amount)) = (event.price, event.amount) {
                     info!(
-                        "Processing Trade event for asset: {}, price: {}, amount: {}, time: {}",
-                        asset, price, amount, event_time
+                        "Processing Trade event for market: {}, price: {}, amount: {}, time: {}",
+                        symbol, price, amount, event_time
                     );
 
-                    // Поддерживаемые интервалы свечей (1m, 3m, 5m, 15m, 1h, 1d, 1w)
-                    let intervals = vec![60, 180, 300, 900, 3600, 86400, 604800];
-                    for &interval in &intervals {
-                        candle_store.add_price(asset, interval, price as f64, amount as f64, event_time);
+                    if let Some(candles) = self.candles.as_ref().filter(|_| !event.counterpart) {
+                        // Остальные разрешения строятся из базовой серии при запросе
+                        let undo = candles.add_trade(symbol, price, amount, event_time)?;
+                        changes.push(StoreChange::Trade(undo));
                     }
+
+                    handle_fill(&order_book, event, amount);
                 } else {
                     error!("Incomplete Trade event data: {:?}", event);
                 }
             }
-            _ => {}
+            OrderEventKind::Cancel => {
+                order_book.remove_order(&event.order_id, event.order_type);
+                info!("Order {} cancelled", event.order_id);
+            }
         }
-    } else {
-        error!("Event type is missing in event: {:?}", event);
+
+        self.applied.lock().unwrap().insert(key.clone());
+        if in_order {
+            self.journal
+                .lock()
+                .unwrap()
+                .record(event.position.height, &event.position.hash, key, changes);
+        }
+        Ok(())
     }
+
+    /// Откатывает изменения блоков, выпавших из канонической цепи.
+    /// Блоки приходят от новых к старым, изменения внутри блока откатываются с конца.
+    fn rollback(&self, orphaned: Vec<AppliedBlock>) {
+        let from = orphaned.last().map(|b| b.height).unwrap_or_default();
+        let to = orphaned.first().map(|b| b.height).unwrap_or_default();
+        warn!(
+            "Chain reorganisation detected, reverting {} blocks ({}..={})",
+            orphaned.len(),
+            from,
+            to
+        );
+
+        for block in orphaned {
+            {
+                let mut applied = self.applied.lock().unwrap();
+                for key in &block.events {
+                    applied.remove(key);
+                }
+            }
+            for change in block.changes.into_iter().rev() {
+                match change {
+                    StoreChange::Trade(undo) => {
+                        if let Some(candles) = &self.candles {
+                            if let Err(e) = candles.revert(undo) {
+                                error!("Failed to revert candle in block {}: {}", block.height, e);
+                            }
+                        }
+                    }
+                    StoreChange::Order {
+                        symbol,
+                        order_id,
+                        previous,
+                    } => {
+                        let order_book = self.order_books.get_or_create(&symbol);
+                        order_book.remove_order(&order_id, None);
+                        if let Some(order) = previous {
+                            order_book.add_order(order);
+                        }
+                    }
+                }
+            }
+            info!("Reverted block {} ({})", block.height, block.hash);
+        }
+
+        // Блоки канонической ветки после форка источник уже пропустил,
+        // пусть перечитает их начиная с форка
+        if let Some(status) = &self.status {
+            status.set_last_block(from - 1);
+        }
+        if let Some(rewinds) = &self.rewinds {
+            if rewinds.send(from).is_ok() {
+                *self.rewinding.lock().unwrap() = true;
+            }
+        }
+    }
+}
+
+fn handle_open_event(order_book: &OrderBook, event: &OrderEvent) {
+    let (Some(order_type), Some(price), Some(amount)) = (event.order_type, event.price, event.amount)
+    else {
+        error!("Incomplete Open event data: {:?}", event);
+        return;
+    };
+
+    let order = SpotOrder {
+        id: event.order_id.clone(),
+        user: event.user.clone().unwrap_or_default(),
+        asset: event.asset.clone().unwrap_or_default(),
+        amount,
+        price,
+        timestamp: event.timestamp as u64,
+        order_type,
+        status: Some(OrderStatus::New),
+    };
+
+    info!(
+        "Order {} opened: {:?} {} @ {}",
+        order.id, order.order_type, order.amount, order.price
+    );
+    order_book.update_order(order);
 }
 
+/// Уменьшает остаток ордера на объем сделки. Полностью исполненный ордер
+/// убирается из книги, частично исполненный остается со статусом `PartiallyMatched`.
+fn handle_fill(order_book: &OrderBook, event: &OrderEvent, filled: u128) {
+    let existing = match event.order_type {
+        Some(order_type) => order_book.get_order(&event.order_id, order_type),
+        None => order_book.find_order(&event.order_id),
+    };
+
+    let Some(mut order) = existing else {
+        warn!("Trade for unknown order {}, skipping book update", event.order_id);
+        return;
+    };
+
+    order.amount = order.amount.saturating_sub(filled);
+    if order.amount == 0 {
+        order_book.remove_order(&order.id, Some(order.order_type));
+        info!("Order {} fully matched", order.id);
+    } else {
+        order.status = Some(OrderStatus::PartiallyMatched);
+        order_book.update_order(order);
+    }
+}
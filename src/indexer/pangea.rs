@@ -1,62 +1,523 @@
-use ethers_core::types::H256;
+use async_trait::async_trait;
 use fuels::accounts::provider::Provider;
-use log::{error, info};
+use log::{error, info, warn};
 use pangea_client::{ChainId, Client};
 use pangea_client::{
-    futures::StreamExt, provider::FuelProvider, query::Bound, requests::fuel::GetSparkOrderRequest,
+    futures::{stream, Stream, StreamExt}, provider::FuelProvider, query::Bound, requests::fuel::GetSparkOrderRequest,
     ClientBuilder, Format, WsProvider,
 };
-use tokio::time::{interval, sleep};
-use std::collections::HashSet;
-use std::str::FromStr;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use crate::config::chain::Chain;
 use crate::config::env::ev;
+use crate::config::markets::Markets;
 use crate::error::Error;
-use crate::indexer::order_event_handler::handle_order_event;
-use crate::indexer::order_event_handler::PangeaOrderEvent;
-use crate::storage::candles::CandleStore;
+use crate::indexer::block_time::BlockTimeResolver;
+use crate::indexer::dead_letter::{DeadLetter, DeadLetterQueue};
+use crate::indexer::pipeline::DEFAULT_REORG_WINDOW_BLOCKS;
+use crate::indexer::recorder::EventRecorder;
+use crate::indexer::source::{
+    BlockPosition, EventSource, Indexer, OrderEvent, OrderEventKind, SourceEvent,
+};
+
+const DEFAULT_BACKFILL_BATCH_SIZE: i64 = 10_000;
+const DEFAULT_BACKFILL_CONCURRENCY: usize = 4;
+const DEFAULT_BACKFILL_MAX_RETRIES: u32 = 5;
+const DEFAULT_BLOCK_TIME_CONCURRENCY: usize = 16;
+const BACKFILL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const BACKFILL_MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
 
-pub async fn initialize_pangea_indexer(
-    tasks: &mut Vec<tokio::task::JoinHandle<()>>,
-    candle_store: Arc<CandleStore>,
-) -> Result<(), Error> {
-    let ws_task_pangea = tokio::spawn(async move {
-        if let Err(e) = start_pangea_indexer(candle_store).await {
-            eprintln!("Pangea error: {}", e);
+/// Настройки догонки истории.
+struct BackfillConfig {
+    batch_size: i64,
+    concurrency: usize,
+    max_retries: u32,
+    /// Пауза перед первым повтором, дальше удваивается.
+    retry_delay: Duration,
+    /// Сколько запросов времени блоков одного диапазона идет одновременно.
+    block_time_concurrency: usize,
+}
+
+impl BackfillConfig {
+    fn from_env() -> Self {
+        Self {
+            batch_size: ev("BACKFILL_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_BACKFILL_BATCH_SIZE),
+            concurrency: ev("BACKFILL_CONCURRENCY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_BACKFILL_CONCURRENCY)
+                .max(1),
+            max_retries: ev("BACKFILL_MAX_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_BACKFILL_MAX_RETRIES)
+                .max(1),
+            retry_delay: BACKFILL_RETRY_DELAY,
+            block_time_concurrency: ev("BLOCK_TIME_CONCURRENCY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_BLOCK_TIME_CONCURRENCY)
+                .max(1),
         }
-    });
+    }
 
-    tasks.push(ws_task_pangea);
-    Ok(())
+    /// Повторяет `attempt` с экспоненциальной паузой, всего не больше
+    /// `max_retries` попыток.
+    async fn retry<T, F, Fut>(&self, what: impl Display, mut attempt: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut retry_delay = self.retry_delay;
+        let mut attempts = 1;
+        loop {
+            match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempts < self.max_retries => {
+                    warn!(
+                        "Failed to fetch {} (attempt {}): {}. Retrying in {:?}...",
+                        what, attempts, e, retry_delay
+                    );
+                    sleep(retry_delay).await;
+                    retry_delay = (retry_delay * 2).min(BACKFILL_MAX_RETRY_DELAY);
+                    attempts += 1;
+                }
+                Err(e) => {
+                    error!("Giving up on {} after {} attempts: {}", what, attempts, e);
+                    return Err(Error::MaxRetriesExceeded);
+                }
+            }
+        }
+    }
 }
 
-async fn start_pangea_indexer(candle_store: Arc<CandleStore>) -> Result<(), Error> {
-    let client = create_pangea_client().await?;
+/// Диапазоны догонки `[from, to)` по `batch_size` блоков, последний
+/// заканчивается на `target_block` включительно.
+///
+/// Включает ли Pangea блок `to_block` в ответ, не документировано. Диапазоны
+/// идут встык, а события с блоком `to` и дальше при применении отбрасываются:
+/// при любой трактовке каждый блок применяется ровно один раз.
+fn backfill_ranges(from_block: i64, target_block: i64, batch_size: i64) -> Vec<(i64, i64)> {
+    let mut ranges = Vec::new();
+    let mut from_block = from_block;
+    while from_block <= target_block {
+        let to_block = (from_block + batch_size.max(1)).min(target_block + 1);
+        ranges.push((from_block, to_block));
+        from_block = to_block;
+    }
+    ranges
+}
+
+/// Загружает диапазоны параллельно, до `concurrency` одновременно,
+/// а результаты отдает строго по порядку диапазонов.
+fn fetch_in_order<T, F, Fut>(
+    ranges: Vec<(i64, i64)>,
+    concurrency: usize,
+    fetch: F,
+) -> impl Stream<Item = Result<T, Error>>
+where
+    F: FnMut((i64, i64)) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    stream::iter(ranges).map(fetch).buffered(concurrency)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PangeaOrderEvent {
+    pub chain: u64,
+    pub block_number: i64,
+    pub block_hash: String,
+    pub transaction_hash: String,
+    pub transaction_index: u64,
+    pub log_index: u64,
+    pub market_id: String,
+    pub order_id: String,
+    pub event_type: Option<String>,
+    pub asset: Option<String>,
+    pub amount: Option<u128>,
+    pub asset_type: Option<String>,
+    pub order_type: Option<String>,
+    pub price: Option<u128>,
+    pub user: Option<String>,
+    pub order_matcher: Option<String>,
+    pub owner: Option<String>,
+    pub limit_type: Option<String>,
+    /// Время блока. Pangea его не отдает, заполняется в payload dead-letter
+    /// записей, чтобы повторная обработка не ходила в ноду.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_timestamp: Option<i64>,
+}
+
+impl PangeaOrderEvent {
+    /// Переводит событие Pangea в нормализованный вид.
+    pub fn into_order_event(self, timestamp: i64) -> Result<OrderEvent, Error> {
+        let kind = match self.event_type.as_deref() {
+            Some("Open") => OrderEventKind::Open,
+            Some("Trade") | Some("Match") => OrderEventKind::Trade,
+            Some("Cancel") => OrderEventKind::Cancel,
+            Some(other) => return Err(Error::UnknownEventType(other.to_string())),
+            None => return Err(Error::UnknownEventType("<missing>".to_string())),
+        };
+
+        Ok(OrderEvent {
+            position: BlockPosition {
+                height: self.block_number,
+                hash: self.block_hash,
+                transaction_hash: self.transaction_hash,
+                transaction_index: self.transaction_index,
+                log_index: self.log_index,
+            },
+            timestamp,
+            market_id: self.market_id,
+            order_id: self.order_id,
+            kind,
+            order_type: self.order_type.as_deref().and_then(|t| t.parse().ok()),
+            asset: self.asset,
+            amount: self.amount,
+            price: self.price,
+            user: self.user.or(self.owner),
+            counterpart: false,
+        })
+    }
+}
 
-    let contract_start_block: i64 = ev("CONTRACT_START_BLOCK")?.parse()?;
-    let contract_h256 = H256::from_str(&ev("CONTRACT_ID")?)?;
+/// Источник событий Spark ордеров из Pangea: сначала история пачками,
+/// затем подписка на новые блоки.
+pub struct PangeaSource {
+    client: Client<WsProvider>,
+    markets: Arc<Markets>,
+    provider: Provider,
+    block_times: BlockTimeResolver,
+    chain: ChainId,
+    contract_start_block: i64,
+    backfill: BackfillConfig,
+    recorder: Option<EventRecorder>,
+    dead_letters: Arc<DeadLetterQueue>,
+    emitted: Mutex<EmittedBlocks>,
+}
 
-    let mut last_processed_block =
-        fetch_historical_data(&client, &candle_store, 
-        contract_start_block, contract_h256).await?;
+impl PangeaSource {
+    pub async fn from_env(
+        markets: Arc<Markets>,
+        chain: Chain,
+        dead_letters: Arc<DeadLetterQueue>,
+    ) -> Result<Self, Error> {
+        let pangea_chain = chain.pangea_chain_id()?;
+        let client = create_pangea_client().await?;
+        let contract_start_block: i64 = ev("CONTRACT_START_BLOCK")?.parse()?;
+        let provider = chain.connect().await?;
+        let recorder = match ev("PANGEA_RECORD_PATH") {
+            Ok(path) => Some(EventRecorder::open(path)?),
+            Err(_) => None,
+        };
 
-    if last_processed_block == 0 {
-        last_processed_block = contract_start_block;
+        Ok(Self {
+            client,
+            markets,
+            block_times: BlockTimeResolver::from_env(provider.clone()),
+            provider,
+            chain: pangea_chain,
+            contract_start_block,
+            backfill: BackfillConfig::from_env(),
+            recorder,
+            dead_letters,
+            emitted: Mutex::new(EmittedBlocks::from_env()),
+        })
     }
 
-    info!("Switching to listening for new orders (deltas)");
+    /// Догоняет историю до текущего блока ноды. Диапазоны по `BACKFILL_BATCH_SIZE`
+    /// блоков запрашиваются параллельно (до `BACKFILL_CONCURRENCY` одновременно),
+    /// но применяются строго по порядку блоков.
+    async fn fetch_historical_data(
+        &self,
+        sink: &mpsc::Sender<SourceEvent>,
+        cursor: &mut EventPosition,
+    ) -> Result<(), Error> {
+        let target_latest_block = self.provider.latest_block_height().await? as i64;
+        info!("Target last block for processing: {}", target_latest_block);
 
-    listen_for_new_deltas(&client, &candle_store,
-        last_processed_block, contract_h256).await
+        let ranges = backfill_ranges(
+            cursor.resume_block(),
+            target_latest_block,
+            self.backfill.batch_size,
+        );
+        if ranges.is_empty() {
+            return Ok(());
+        }
+        info!(
+            "Backfilling {} ranges with concurrency {}",
+            ranges.len(),
+            self.backfill.concurrency
+        );
+
+        let batches = fetch_in_order(ranges, self.backfill.concurrency, |(from_block, to_block)| {
+            self.fetch_range_with_retry(from_block, to_block)
+        });
+        pangea_client::futures::pin_mut!(batches);
+
+        while let Some(batch) = batches.next().await {
+            let (to_block, payloads) = batch?;
+            let events: Vec<_> = payloads
+                .into_iter()
+                .filter_map(|payload| self.parse_payload(payload))
+                // Блок `to_block`, если Pangea его включила, придет следующим диапазоном
+                .filter(|event| event.block_number < to_block)
+                .collect();
+            // Время блоков диапазона запрашиваем разом, а не по одному на событие
+            self.block_times
+                .prefetch(
+                    events.iter().map(|event| event.block_number),
+                    self.backfill.block_time_concurrency,
+                )
+                .await?;
+            for event in events {
+                self.emit_event(sink, event, cursor).await?;
+            }
+
+            let last_block = to_block - 1;
+            *cursor = EventPosition::block_end(last_block);
+            send(sink, SourceEvent::BlockCompleted(last_block)).await?;
+            info!(
+                "Processed events up to block {}. Moving to the next batch...",
+                last_block
+            );
+        }
+        Ok(())
+    }
+
+    /// Загружает диапазон `[from_block, to_block)` целиком, повторяя запрос
+    /// с экспоненциальной паузой.
+    async fn fetch_range_with_retry(
+        &self,
+        from_block: i64,
+        to_block: i64,
+    ) -> Result<(i64, Vec<Vec<u8>>), Error> {
+        let range = format!("blocks {}..{}", from_block, to_block);
+        let payloads = self
+            .backfill
+            .retry(range, || self.fetch_range(from_block, to_block))
+            .await?;
+        Ok((to_block, payloads))
+    }
+
+    /// Загружает сырые события диапазона. Разбор откладывается до применения,
+    /// чтобы неразбираемые записи не роняли диапазон и не дублировались при повторах.
+    async fn fetch_range(&self, from_block: i64, to_block: i64) -> Result<Vec<Vec<u8>>, Error> {
+        let request_batch = GetSparkOrderRequest {
+            from_block: Bound::Exact(from_block),
+            to_block: Bound::Exact(to_block),
+            market_id__in: self.markets.ids(),
+            chains: HashSet::from([self.chain]),
+            ..Default::default()
+        };
+
+        let stream_batch = self
+            .client
+            .get_fuel_spark_orders_by_format(request_batch, Format::JsonStream, false)
+            .await?;
+        pangea_client::futures::pin_mut!(stream_batch);
+
+        // Обрыв стрима посреди диапазона — ошибка всего диапазона,
+        // иначе хвост событий потеряется молча
+        let mut payloads = Vec::new();
+        while let Some(data) = stream_batch.next().await {
+            payloads.push(data?);
+        }
+        Ok(payloads)
+    }
+
+    /// Слушает новые блоки, плановым переподключением освежая соединение.
+    /// Ошибки возвращаются супервизору, он перезапустит источник с паузой.
+    async fn listen_for_new_deltas(
+        &self,
+        sink: &mpsc::Sender<SourceEvent>,
+        mut cursor: EventPosition,
+    ) -> Result<(), Error> {
+        let reconnect_interval = Duration::from_secs(10*60);
+
+        loop {
+            // Перед подпиской догоняем пропущенные блоки через исторический путь
+            let gap_start = cursor.resume_block();
+            self.fetch_historical_data(sink, &mut cursor).await?;
+            let backfilled = cursor.resume_block() - gap_start;
+            if backfilled > 0 {
+                info!(
+                    "Backfilled {} blocks ({}..{}) before resuming the delta stream",
+                    backfilled,
+                    gap_start,
+                    cursor.resume_block()
+                );
+            }
+
+            let request_deltas = GetSparkOrderRequest {
+                from_block: Bound::Exact(cursor.resume_block()),
+                to_block: Bound::Subscribe,
+                market_id__in: self.markets.ids(),
+                chains: HashSet::from([self.chain]),
+                ..Default::default()
+            };
+
+            let stream_deltas = self
+                .client
+                .get_fuel_spark_orders_by_format(request_deltas, Format::JsonStream, true)
+                .await?;
+            pangea_client::futures::pin_mut!(stream_deltas);
+            let reconnect_timer = sleep(reconnect_interval);
+            tokio::pin!(reconnect_timer);
+
+            loop {
+                tokio::select! {
+                    _ = &mut reconnect_timer => {
+                        info!("Scheduled reconnect to refresh connection...");
+                        break;
+                    }
+                    data_result = stream_deltas.next() => match data_result {
+                        Some(Ok(data)) => self.process_order_data(&data, sink, &mut cursor).await?,
+                        Some(Err(e)) => return Err(e.into()),
+                        None => {
+                            return Err(Error::SourceDisconnected(
+                                "Pangea delta stream ended".to_string(),
+                            ))
+                        }
+                    }
+                }
+            }
+            info!("Resuming after block position {:?}", cursor);
+        }
+    }
+
+    async fn process_order_data(
+        &self,
+        data: &[u8],
+        sink: &mpsc::Sender<SourceEvent>,
+        cursor: &mut EventPosition,
+    ) -> Result<(), Error> {
+        let Some(order_event) = self.parse_payload(data.to_vec()) else {
+            return Ok(());
+        };
+        // Событие из нового блока означает, что предыдущие блоки отданы полностью
+        if order_event.block_number > cursor.block {
+            send(sink, SourceEvent::BlockCompleted(order_event.block_number - 1)).await?;
+        }
+        self.emit_event(sink, order_event, cursor).await
+    }
+
+    /// Разбирает сырое событие. Неразбираемое, включая не-UTF-8, уходит
+    /// в dead-letter очередь, и обработка идет дальше. Запись в `recorder`
+    /// идет до разбора и фильтрации, байты как пришли от Pangea.
+    fn parse_payload(&self, data: Vec<u8>) -> Option<PangeaOrderEvent> {
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = recorder.record(&data) {
+                error!("Failed to record Pangea payload: {}", e);
+            }
+        }
+        let payload = match String::from_utf8(data) {
+            Ok(payload) => payload,
+            Err(e) => {
+                let payload = String::from_utf8_lossy(e.as_bytes()).into_owned();
+                self.dead_letters
+                    .push(DeadLetter::parse_failure(Indexer::Pangea, &payload, &e.into()));
+                return None;
+            }
+        };
+        match serde_json::from_str(&payload) {
+            Ok(event) => Some(event),
+            Err(e) => {
+                self.dead_letters
+                    .push(DeadLetter::parse_failure(Indexer::Pangea, &payload, &e.into()));
+                None
+            }
+        }
+    }
+
+    /// Отдает событие, если оно идет после `cursor` или пришло из ветки,
+    /// заменившей уже отданный блок, и сдвигает курсор.
+    async fn emit_event(
+        &self,
+        sink: &mpsc::Sender<SourceEvent>,
+        mut event: PangeaOrderEvent,
+        cursor: &mut EventPosition,
+    ) -> Result<(), Error> {
+        let position = EventPosition::of(&event);
+        if !self.emitted.lock().unwrap().admits(&event, cursor) {
+            return Ok(());
+        }
+
+        let timestamp = self.block_times.timestamp(event.block_number).await?;
+        event.block_timestamp = Some(timestamp);
+        // Payload с уже известным временем блока, чтобы повторная обработка не ходила в ноду
+        let payload = serde_json::to_string(&event)?;
+        let block_hash = event.block_hash.clone();
+        match event.into_order_event(timestamp) {
+            Ok(order_event) => send(sink, SourceEvent::Order(order_event)).await?,
+            Err(e) => self
+                .dead_letters
+                .push(DeadLetter::parse_failure(Indexer::Pangea, &payload, &e)),
+        }
+        self.emitted
+            .lock()
+            .unwrap()
+            .record(position.block, &block_hash);
+        *cursor = position.max(*cursor);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSource for PangeaSource {
+    fn indexer(&self) -> Indexer {
+        Indexer::Pangea
+    }
+
+    async fn run(&self, last_block: Option<i64>, sink: mpsc::Sender<SourceEvent>) -> Result<(), Error> {
+        let start_block = match last_block {
+            Some(last_block) => {
+                info!("Resuming Pangea from block {}", last_block + 1);
+                last_block + 1
+            }
+            None => self.contract_start_block,
+        };
+
+        // Блоки после точки старта отдаются заново и могут оказаться в другой ветке
+        self.emitted.lock().unwrap().forget_after(start_block - 1);
+        let mut cursor = EventPosition::block_end(start_block - 1);
+        self.fetch_historical_data(&sink, &mut cursor).await?;
+        send(&sink, SourceEvent::CaughtUp).await?;
+
+        info!("Switching to listening for new orders (deltas)");
+
+        self.listen_for_new_deltas(&sink, cursor).await
+    }
+
+    async fn decode_payload(&self, payload: &str) -> Result<OrderEvent, Error> {
+        let event: PangeaOrderEvent = serde_json::from_str(payload)?;
+        let timestamp = match event.block_timestamp {
+            Some(timestamp) => timestamp,
+            None => self.block_times.timestamp(event.block_number).await?,
+        };
+        event.into_order_event(timestamp)
+    }
+}
+
+async fn send(sink: &mpsc::Sender<SourceEvent>, event: SourceEvent) -> Result<(), Error> {
+    sink.send(event).await.map_err(|_| Error::SinkClosed)
 }
 
 async fn create_pangea_client() -> Result<Client<WsProvider>, Error> {
 
-    let username = ev("PANGEA_USERNAME")?; 
-    let password = ev("PANGEA_PASSWORD")?; 
-    let url = ev("PANGEA_URL")?; 
+    let username = ev("PANGEA_USERNAME")?;
+    let password = ev("PANGEA_PASSWORD")?;
+    let url = ev("PANGEA_URL")?;
 
     let client = ClientBuilder::default()
         .endpoint(&url)
@@ -68,165 +529,172 @@ async fn create_pangea_client() -> Result<Client<WsProvider>, Error> {
     Ok(client)
 }
 
-async fn get_latest_block(chain_id: ChainId) -> Result<i64, Error> {
-    let provider_url = match chain_id {
-        ChainId::FUEL => Ok("mainnet.fuel.network"),
-        ChainId::FUELTESTNET => Ok("testnet.fuel.network"),
-        _ => Err(Error::UnknownChainIdError)
-    }?;
-    let provider = Provider::connect(provider_url).await?;
-    Ok(provider.latest_block_height().await.map(|height| height as i64)?)
+/// Позиция последнего отданного события в цепи.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct EventPosition {
+    block: i64,
+    transaction_index: u64,
+    log_index: u64,
 }
 
-async fn fetch_historical_data(
-    client: &Client<WsProvider>,
-    candle_store: &Arc<CandleStore>,
-    contract_start_block: i64,
-    contract_h256: H256,
-) -> Result<i64, Error> {
-    let fuel_chain = match ev("CHAIN")?.as_str() { 
-        "FUEL" => ChainId::FUEL,
-        _ => ChainId::FUELTESTNET,
-    };
-    let batch_size = 10_000;
-    let mut last_processed_block = contract_start_block;
+impl EventPosition {
+    pub(crate) fn of(event: &PangeaOrderEvent) -> Self {
+        Self {
+            block: event.block_number,
+            transaction_index: event.transaction_index,
+            log_index: event.log_index,
+        }
+    }
 
-    let target_latest_block = get_latest_block(fuel_chain).await?;
-    info!("Target last block for processing: {}", target_latest_block);
+    /// Позиция после всех событий блока `block`.
+    pub(crate) fn block_end(block: i64) -> Self {
+        Self {
+            block,
+            transaction_index: u64::MAX,
+            log_index: u64::MAX,
+        }
+    }
 
-    while last_processed_block < target_latest_block {
-        let to_block = (last_processed_block + batch_size).min(target_latest_block);
+    /// Первый блок, который нужно запросить, чтобы продолжить сразу после позиции.
+    fn resume_block(&self) -> i64 {
+        if *self == Self::block_end(self.block) {
+            self.block + 1
+        } else {
+            self.block
+        }
+    }
+}
 
-        let request_batch = GetSparkOrderRequest {
-            from_block: Bound::Exact(last_processed_block),
-            to_block: Bound::Exact(to_block),
-            market_id__in: HashSet::from([contract_h256]),
-            chains: HashSet::from([fuel_chain]),
-            ..Default::default()
-        };
+/// Хэши последних отданных блоков. По ним событие ветки, заменившей
+/// уже отданный блок, отличается от повтора.
+pub(crate) struct EmittedBlocks {
+    hashes: BTreeMap<i64, String>,
+    capacity: usize,
+}
 
-        let stream_batch = client
-            .get_fuel_spark_orders_by_format(request_batch, Format::JsonStream, false)
-            .await
-            .expect("Failed to get fuel spark orders batch");
+impl EmittedBlocks {
+    fn new(capacity: usize) -> Self {
+        Self {
+            hashes: BTreeMap::new(),
+            capacity,
+        }
+    }
 
-        pangea_client::futures::pin_mut!(stream_batch);
+    /// Хранит столько блоков, сколько покрывает окно реорганизаций.
+    pub(crate) fn from_env() -> Self {
+        Self::new(
+            ev("REORG_WINDOW_BLOCKS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_REORG_WINDOW_BLOCKS),
+        )
+    }
 
-        while let Some(data) = stream_batch.next().await {
-            match data {
-                Ok(data) => {
-                    let data = String::from_utf8(data)?;
-                    let order: PangeaOrderEvent = serde_json::from_str(&data)?;
-                    handle_order_event(candle_store.clone(), 
-                        order).await;
-                }
-                Err(e) => {
-                    error!("Error in the stream of historical orders: {e}");
-                    break;
-                }
-            }
+    /// Событие идет после `cursor` или пришло из ветки, заменившей уже
+    /// отданный блок. Повтор отданного блока отбрасывается, а событие с другим
+    /// хэшем пропускается: по нему потребитель увидит реорганизацию.
+    pub(crate) fn admits(&self, event: &PangeaOrderEvent, cursor: &EventPosition) -> bool {
+        if EventPosition::of(event) > *cursor {
+            return true;
         }
-
-        last_processed_block = to_block;
-        info!(
-            "Processed events up to block {}. Moving to the next batch...",
-            last_processed_block
+        if !self.replaced(event.block_number, &event.block_hash) {
+            return false;
+        }
+        warn!(
+            "Block {} replaced by {}, passing the event on",
+            event.block_number, event.block_hash
         );
+        true
     }
-    Ok(last_processed_block)
-}
-
-
-async fn listen_for_new_deltas(
-    client: &Client<WsProvider>,
-    candle_store: &Arc<CandleStore>,
-    mut last_processed_block: i64,
-    contract_h256: H256,
-) -> Result<(), Error> {
-    let mut retry_delay = Duration::from_secs(1);
-    let reconnect_interval = Duration::from_secs(10*60); 
-    let mut reconnect_timer = interval(reconnect_interval);
-    let mut processing = false;
-
-    loop {
-        tokio::select! {
-            _ = reconnect_timer.tick(), if !processing => {
-                info!("Scheduled reconnect to refresh connection...");
-                processing = false; 
-                let fuel_chain = match ev("CHAIN")?.as_str() {
-                    "FUEL" => ChainId::FUEL,
-                    _ => ChainId::FUELTESTNET,
-                };
-                let latest_block = get_latest_block(fuel_chain).await?;
-                let buffer_blocks = 10; 
-                last_processed_block = latest_block.saturating_sub(buffer_blocks);
-                info!("Updated last_processed_block to {}", last_processed_block);
-            },
-            result = async {
-                processing = true;
-                let fuel_chain = match ev("CHAIN")?.as_str() {
-                    "FUEL" => ChainId::FUEL,
-                    _ => ChainId::FUELTESTNET,
-                };
-
-                let request_deltas = GetSparkOrderRequest {
-                    from_block: Bound::Exact(last_processed_block + 1),
-                    to_block: Bound::Subscribe,
-                    market_id__in: HashSet::from([contract_h256]),
-                    chains: HashSet::from([fuel_chain]),
-                    ..Default::default()
-                };
-
-                match client
-                    .get_fuel_spark_orders_by_format(request_deltas, Format::JsonStream, true)
-                    .await
-                {
-                    Ok(stream_deltas) => {
-                        retry_delay = Duration::from_secs(1);
-                        pangea_client::futures::pin_mut!(stream_deltas);
-
-                        while let Some(data_result) = stream_deltas.next().await {
-                            match data_result {
-                                Ok(data) => {
-                                    if let Err(e) = process_order_data(&data, candle_store, &mut last_processed_block).await {
-                                        error!("Failed to process order data: {}", e);
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Error in the stream of new orders (deltas): {}", e);
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to initiate stream: {}", e);
-                    }
-                }
 
-                info!("Reconnecting to listen for new deltas in {} seconds...", retry_delay.as_secs());
-                sleep(retry_delay).await;
-                retry_delay = (retry_delay * 2).min(Duration::from_secs(60));
-                Ok::<(), Error>(())
-            } => {
-                processing = false;
-                if let Err(e) = result {
-                    error!("Error in listen_for_new_deltas: {:?}", e);
-                }
-            },
+    /// Блок `height` уже отдавался, но с другим хэшем.
+    fn replaced(&self, height: i64, hash: &str) -> bool {
+        self.hashes.get(&height).is_some_and(|known| known != hash)
+    }
+
+    pub(crate) fn record(&mut self, height: i64, hash: &str) {
+        self.hashes.insert(height, hash.to_string());
+        while self.hashes.len() > self.capacity {
+            self.hashes.pop_first();
         }
     }
+
+    fn forget_after(&mut self, height: i64) {
+        self.hashes.split_off(&(height + 1));
+    }
 }
 
-async fn process_order_data(
-    data: &[u8],
-    candle_store: &Arc<CandleStore>,
-    last_processed_block: &mut i64,
-) -> Result<(), Error> {
-    let data_str = String::from_utf8(data.to_vec())?;
-    let order_event: PangeaOrderEvent = serde_json::from_str(&data_str)?;
-    *last_processed_block = order_event.block_number;
-    handle_order_event(candle_store.clone(),
-        order_event).await;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn backfill(max_retries: u32) -> BackfillConfig {
+        BackfillConfig {
+            batch_size: 10,
+            concurrency: 5,
+            max_retries,
+            retry_delay: Duration::from_millis(1),
+            block_time_concurrency: 1,
+        }
+    }
+
+    #[test]
+    fn backfill_ranges_cover_each_block_once() {
+        assert_eq!(
+            backfill_ranges(100, 125, 10),
+            [(100, 110), (110, 120), (120, 126)]
+        );
+        assert_eq!(backfill_ranges(5, 5, 10), [(5, 6)]);
+        assert!(backfill_ranges(100, 99, 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_are_applied_in_block_order() {
+        let ranges = backfill_ranges(0, 49, 10);
+        let completed = Mutex::new(Vec::new());
+        // Поздние диапазоны загружаются быстрее ранних
+        let batches = fetch_in_order(ranges.clone(), 5, |(from_block, to_block)| {
+            let completed = &completed;
+            async move {
+                sleep(Duration::from_millis(60 - from_block as u64)).await;
+                completed.lock().unwrap().push(from_block);
+                Ok::<_, Error>((from_block, to_block))
+            }
+        });
+        let applied: Vec<_> = batches.map(Result::unwrap).collect().await;
+
+        assert_eq!(applied, ranges);
+        assert_eq!(completed.lock().unwrap().first(), Some(&40));
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up_after_max_retries() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), Error> = backfill(3)
+            .retry("blocks 0..10", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Error::SourceDisconnected("down".to_string()))
+            })
+            .await;
+
+        assert!(matches!(result, Err(Error::MaxRetriesExceeded)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_returns_first_success() {
+        let attempts = AtomicU32::new(0);
+        let result = backfill(3)
+            .retry("blocks 0..10", || async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(Error::SourceDisconnected("down".to_string())),
+                    attempt => Ok(attempt),
+                }
+            })
+            .await;
+
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
 }
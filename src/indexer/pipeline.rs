@@ -0,0 +1,208 @@
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::config::chain::Chain;
+use crate::config::env::ev;
+use crate::config::markets::Markets;
+use crate::error::Error;
+use crate::indexer::block_time::BlockTimeResolver;
+use crate::indexer::dead_letter::{DeadLetterQueue, DeadLetterReprocessor};
+use crate::indexer::envio::EnvioSource;
+use crate::indexer::fuel_node::FuelNodeSource;
+use crate::indexer::order_event_handler::OrderEventHandler;
+use crate::indexer::pangea::PangeaSource;
+use crate::indexer::replay::{ReplayPace, ReplaySource};
+use crate::indexer::source::{EventSource, Indexer};
+use crate::indexer::subsquid::SubsquidSource;
+use crate::indexer::supervisor::{supervise, IndexerStatus, IndexerStatuses, RestartPolicy};
+use crate::storage::timeframes::Timeframes;
+use crate::storage::checkpoint::CheckpointStore;
+use crate::storage::order_book::{IndexerOrderBooks, OrderBooks};
+
+const DEFAULT_CHECKPOINT_PATH: &str = "checkpoint.json";
+const DEFAULT_CHECKPOINT_INTERVAL_SECS: u64 = 30;
+pub(crate) const DEFAULT_REORG_WINDOW_BLOCKS: usize = 100;
+const DEFAULT_DEDUP_CAPACITY: usize = 100_000;
+const DEFAULT_DEAD_LETTER_PATH: &str = "dead_letters.jsonl";
+/// Сколько событий источник может опередить потребителя.
+const EVENT_CHANNEL_CAPACITY: usize = 10_000;
+
+/// Запущенные индексаторы: их книги, dead-letter очередь и состояния задач.
+pub struct Indexers {
+    pub order_books: Arc<IndexerOrderBooks>,
+    pub dead_letters: Arc<DeadLetterReprocessor>,
+    pub statuses: Arc<IndexerStatuses>,
+}
+
+/// Запускает все индексаторы из `INDEXERS` (через запятую, первый — основной)
+/// и потребителей их событий. У каждого индексатора свои книги ордеров,
+/// свечи строит только основной. Необработанные события всех индексаторов
+/// складываются в общую dead-letter очередь из `DEAD_LETTER_PATH`.
+/// Упавшие источники перезапускает супервизор.
+pub async fn initialize_indexers(
+    tasks: &mut Vec<tokio::task::JoinHandle<()>>,
+    timeframes: Arc<Timeframes>,
+    markets: Arc<Markets>,
+    chain: Option<Chain>,
+) -> Result<Indexers, Error> {
+    let indexers = configured_indexers()?;
+    let primary = indexers[0];
+    let mut books = HashMap::new();
+    let dead_letters = Arc::new(DeadLetterQueue::new(
+        ev("DEAD_LETTER_PATH").unwrap_or_else(|_| DEFAULT_DEAD_LETTER_PATH.to_string()),
+    ));
+    let mut reprocessor = DeadLetterReprocessor::new(Arc::clone(&dead_letters));
+    let mut statuses = HashMap::new();
+    let policy = RestartPolicy::from_env();
+
+    for indexer in indexers {
+        let order_books = Arc::new(OrderBooks::new());
+        let candles = (indexer == primary).then(|| Arc::clone(&timeframes));
+        let checkpoint_path = checkpoint_path(indexer, indexer == primary);
+
+        let source = create_source(indexer, &markets, chain, &dead_letters).await?;
+        let handler = create_handler(candles, Arc::clone(&order_books), Arc::clone(&markets), checkpoint_path);
+        let handler = handler.with_dead_letters(indexer, Arc::clone(&dead_letters));
+        let last_block = handler.restore_checkpoint()?;
+        let status = Arc::new(IndexerStatus::new(indexer, last_block));
+        let (rewind_requests, rewinds) = mpsc::unbounded_channel();
+        let handler = handler
+            .with_status(Arc::clone(&status))
+            .with_rewinds(rewind_requests);
+
+        let (sink, events) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        reprocessor.register(Arc::clone(&source), sink.clone());
+        let consumer = tokio::spawn(async move {
+            handler.consume(events).await;
+        });
+        // Паника потребителя иначе видна только как остановка источника
+        let consumer_status = Arc::clone(&status);
+        let consumer_task = tokio::spawn(async move {
+            if let Err(e) = consumer.await {
+                error!("Indexer {:?} consumer crashed: {}", indexer, e);
+                consumer_status.fail(format!("Event consumer crashed: {}", e));
+            }
+        });
+        let source_task = tokio::spawn(supervise(source, sink, Arc::clone(&status), policy, rewinds));
+
+        info!("Indexer {:?} started", indexer);
+        tasks.push(consumer_task);
+        tasks.push(source_task);
+        books.insert(indexer, order_books);
+        statuses.insert(indexer, status);
+    }
+
+    Ok(Indexers {
+        order_books: Arc::new(IndexerOrderBooks::new(primary, books)),
+        dead_letters: Arc::new(reprocessor),
+        statuses: Arc::new(IndexerStatuses::new(statuses)),
+    })
+}
+
+fn configured_indexers() -> Result<Vec<Indexer>, Error> {
+    let value = ev("INDEXERS")
+        .or_else(|_| ev("INDEXER"))
+        .unwrap_or_else(|_| "pangea".to_string());
+
+    let mut indexers: Vec<Indexer> = Vec::new();
+    for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let indexer: Indexer = name.parse()?;
+        if indexers.contains(&indexer) {
+            return Err(Error::ConfigError(format!("Indexer {} listed twice", name)));
+        }
+        indexers.push(indexer);
+    }
+    if indexers.is_empty() {
+        return Err(Error::ConfigError("No indexers configured".to_string()));
+    }
+    Ok(indexers)
+}
+
+/// Основной индексатор пишет чекпоинт в `CHECKPOINT_PATH`,
+/// остальные — в соседний файл с именем индексатора.
+fn checkpoint_path(indexer: Indexer, primary: bool) -> PathBuf {
+    let path = PathBuf::from(ev("CHECKPOINT_PATH").unwrap_or_else(|_| DEFAULT_CHECKPOINT_PATH.to_string()));
+    if primary {
+        return path;
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "checkpoint".to_string());
+    path.with_file_name(format!("{}.{}.json", stem, indexer.as_str()))
+}
+
+async fn create_source(
+    indexer: Indexer,
+    markets: &Arc<Markets>,
+    chain: Option<Chain>,
+    dead_letters: &Arc<DeadLetterQueue>,
+) -> Result<Arc<dyn EventSource>, Error> {
+    Ok(match indexer {
+        Indexer::Pangea => Arc::new(
+            PangeaSource::from_env(
+                Arc::clone(markets),
+                required_chain(chain, indexer)?,
+                Arc::clone(dead_letters),
+            )
+            .await?,
+        ),
+        Indexer::FuelNode => Arc::new(
+            FuelNodeSource::from_env(Arc::clone(markets), required_chain(chain, indexer)?).await?,
+        ),
+        Indexer::Envio => Arc::new(EnvioSource::new(ev("ENVIO_WS_URL")?, markets)),
+        Indexer::Subsquid => Arc::new(SubsquidSource::new(ev("SUBSQUID_WS_URL")?, markets)),
+        Indexer::Replay => {
+            let source = ReplaySource::new(
+                ev("REPLAY_PATH")?,
+                ev("REPLAY_PACE")
+                    .unwrap_or_else(|_| "full".to_string())
+                    .parse::<ReplayPace>()?,
+                Arc::clone(dead_letters),
+            );
+            Arc::new(match chain {
+                Some(chain) => source.with_block_times(BlockTimeResolver::from_env(chain.connect().await?)),
+                None => source,
+            })
+        }
+    })
+}
+
+/// Бэкендам, которые ходят в Fuel ноду, нужна заданная сеть.
+fn required_chain(chain: Option<Chain>, indexer: Indexer) -> Result<Chain, Error> {
+    chain.ok_or_else(|| Error::ConfigError(format!("CHAIN is required for the {} indexer", indexer.as_str())))
+}
+
+fn create_handler(
+    candles: Option<Arc<Timeframes>>,
+    order_books: Arc<OrderBooks>,
+    markets: Arc<Markets>,
+    checkpoint_path: PathBuf,
+) -> OrderEventHandler {
+    let checkpoint_interval = ev("CHECKPOINT_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CHECKPOINT_INTERVAL_SECS);
+    let checkpoints = CheckpointStore::new(checkpoint_path, Duration::from_secs(checkpoint_interval));
+    let reorg_window = ev("REORG_WINDOW_BLOCKS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_REORG_WINDOW_BLOCKS);
+    let dedup_capacity = ev("DEDUP_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_DEDUP_CAPACITY);
+
+    OrderEventHandler::new(
+        candles,
+        order_books,
+        markets,
+        checkpoints,
+        reorg_window,
+        dedup_capacity,
+    )
+}
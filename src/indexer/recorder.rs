@@ -0,0 +1,34 @@
+use log::info;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::Error;
+
+/// Пишет ответы Pangea в файл байт в байт, по одному на строку, до разбора
+/// и фильтрации. Такой файл потом проигрывается через `ReplaySource`.
+pub struct EventRecorder {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl EventRecorder {
+    /// Открывает файл на дозапись, чтобы рестарт не затирал уже записанное.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path.as_ref())?;
+        info!("Recording Pangea events to {}", path.as_ref().display());
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn record(&self, payload: &[u8]) -> Result<(), Error> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(payload)?;
+        if !payload.ends_with(b"\n") {
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
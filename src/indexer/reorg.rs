@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+
+use crate::indexer::dedup::EventKey;
+use crate::indexer::spot_order::SpotOrder;
+use crate::storage::timeframes::TradeUndo;
+
+/// Изменение стора, которое можно откатить.
+#[derive(Debug, Clone)]
+pub enum StoreChange {
+    Trade(TradeUndo),
+    Order {
+        symbol: String,
+        order_id: String,
+        /// Состояние ордера до изменения, `None` если ордера не было в книге.
+        previous: Option<SpotOrder>,
+    },
+}
+
+/// Примененный блок и изменения, которые он внес в сторы.
+#[derive(Debug)]
+pub struct AppliedBlock {
+    pub height: i64,
+    pub hash: String,
+    pub events: Vec<EventKey>,
+    pub changes: Vec<StoreChange>,
+}
+
+/// Окно последних примененных блоков для обнаружения реорганизаций.
+pub struct ReorgJournal {
+    depth: usize,
+    blocks: VecDeque<AppliedBlock>,
+}
+
+impl ReorgJournal {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            blocks: VecDeque::new(),
+        }
+    }
+
+    /// Проверяет блок события против окна. Если на этой высоте уже был применен
+    /// блок с другим хэшем, или событие пришло на высоту ниже вершины окна,
+    /// которой в окне нет, возвращает отброшенные блоки от новых к старым.
+    pub fn detect_reorg(&mut self, height: i64, hash: &str) -> Vec<AppliedBlock> {
+        let Some(top) = self.blocks.back().map(|b| b.height) else {
+            return vec![];
+        };
+        let oldest = self.blocks.front().map(|b| b.height).unwrap_or(top);
+
+        let forked = match self.blocks.iter().find(|b| b.height == height) {
+            Some(block) => block.hash != hash,
+            None => height < top && height >= oldest,
+        };
+        if !forked {
+            return vec![];
+        }
+
+        let mut orphaned = Vec::new();
+        while self.blocks.back().map_or(false, |b| b.height >= height) {
+            if let Some(block) = self.blocks.pop_back() {
+                orphaned.push(block);
+            }
+        }
+        orphaned
+    }
+
+    /// Записывает изменения, внесенные событием `event` из блока `height`.
+    pub fn record(&mut self, height: i64, hash: &str, event: EventKey, changes: Vec<StoreChange>) {
+        match self.blocks.back_mut() {
+            Some(block) if block.height == height => {
+                block.events.push(event);
+                block.changes.extend(changes);
+            }
+            _ => {
+                self.blocks.push_back(AppliedBlock {
+                    height,
+                    hash: hash.to_string(),
+                    events: vec![event],
+                    changes,
+                });
+                while self.blocks.len() > self.depth {
+                    self.blocks.pop_front();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use async_trait::async_trait;
+use log::{info, warn};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+use crate::error::Error;
+use crate::indexer::block_time::BlockTimeResolver;
+use crate::indexer::dead_letter::{DeadLetter, DeadLetterQueue};
+use crate::indexer::pangea::{EmittedBlocks, EventPosition, PangeaOrderEvent};
+use crate::indexer::source::{EventSource, Indexer, OrderEvent, SourceEvent};
+
+/// Скорость проигрывания записи.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayPace {
+    /// Без пауз, так быстро, как успевает потребитель.
+    Full,
+    /// С паузами по разнице времени блоков, как это было в сети.
+    RealTime,
+}
+
+impl FromStr for ReplayPace {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(ReplayPace::Full),
+            "realtime" | "real-time" => Ok(ReplayPace::RealTime),
+            other => Err(Error::ConfigError(format!("Unknown replay pace: {}", other))),
+        }
+    }
+}
+
+/// Проигрывает файл, записанный `EventRecorder`, без доступа к Pangea.
+/// Ответы разбираются и фильтруются так же, как в живом источнике,
+/// неразбираемые уходят в dead-letter очередь.
+pub struct ReplaySource {
+    path: PathBuf,
+    pace: ReplayPace,
+    block_times: Option<BlockTimeResolver>,
+    dead_letters: Arc<DeadLetterQueue>,
+}
+
+impl ReplaySource {
+    pub fn new(path: impl Into<PathBuf>, pace: ReplayPace, dead_letters: Arc<DeadLetterQueue>) -> Self {
+        Self {
+            path: path.into(),
+            pace,
+            block_times: None,
+            dead_letters,
+        }
+    }
+
+    /// Сырые ответы Pangea не содержат времени блоков, без ноды
+    /// проигрываются только записи с `block_timestamp`.
+    pub fn with_block_times(mut self, block_times: BlockTimeResolver) -> Self {
+        self.block_times = Some(block_times);
+        self
+    }
+
+    /// Время блока события: из записи или от ноды, `None` если взять неоткуда.
+    async fn block_timestamp(&self, event: &PangeaOrderEvent) -> Result<Option<i64>, Error> {
+        match (event.block_timestamp, &self.block_times) {
+            (Some(timestamp), _) => Ok(Some(timestamp)),
+            (None, Some(block_times)) => Ok(Some(block_times.timestamp(event.block_number).await?)),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSource for ReplaySource {
+    fn indexer(&self) -> Indexer {
+        Indexer::Replay
+    }
+
+    async fn run(&self, last_block: Option<i64>, sink: mpsc::Sender<SourceEvent>) -> Result<(), Error> {
+        let file = File::open(&self.path).await?;
+        // Ответы не обязаны быть UTF-8, поэтому читаем байтами
+        let mut lines = BufReader::new(file).split(b'\n');
+        info!("Replaying {} at {:?} pace", self.path.display(), self.pace);
+
+        let mut cursor = EventPosition::block_end(last_block.unwrap_or(-1));
+        let mut emitted = EmittedBlocks::from_env();
+        let mut current_block: Option<i64> = None;
+        let mut previous_time: Option<i64> = None;
+        let mut replayed = 0usize;
+
+        while let Some(line) = lines.next_segment().await? {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let mut event: PangeaOrderEvent = match serde_json::from_slice(&line) {
+                Ok(event) => event,
+                Err(e) => {
+                    let payload = String::from_utf8_lossy(&line);
+                    self.dead_letters
+                        .push(DeadLetter::parse_failure(Indexer::Replay, &payload, &e.into()));
+                    continue;
+                }
+            };
+            if !emitted.admits(&event, &cursor) {
+                continue;
+            }
+            let timestamp = match self.block_timestamp(&event).await? {
+                Some(timestamp) => timestamp,
+                None => {
+                    warn!(
+                        "Skipping recorded event at block {}: no block timestamp, set CHAIN to resolve it",
+                        event.block_number
+                    );
+                    continue;
+                }
+            };
+
+            if let Some(block) = current_block.filter(|&b| b < event.block_number) {
+                send(&sink, SourceEvent::BlockCompleted(block)).await?;
+            }
+            current_block = Some(event.block_number);
+
+            if self.pace == ReplayPace::RealTime {
+                if let Some(previous) = previous_time {
+                    let pause = (timestamp - previous).max(0) as u64;
+                    if pause > 0 {
+                        sleep(Duration::from_secs(pause)).await;
+                    }
+                }
+                previous_time = Some(timestamp);
+            }
+
+            let position = EventPosition::of(&event);
+            emitted.record(position.block, &event.block_hash);
+            cursor = position.max(cursor);
+
+            // Payload с временем блока, чтобы повторная обработка не ходила в ноду
+            event.block_timestamp = Some(timestamp);
+            let payload = serde_json::to_string(&event)?;
+            match event.into_order_event(timestamp) {
+                Ok(order_event) => {
+                    send(&sink, SourceEvent::Order(order_event)).await?;
+                    replayed += 1;
+                }
+                Err(e) => self
+                    .dead_letters
+                    .push(DeadLetter::parse_failure(Indexer::Replay, &payload, &e)),
+            }
+        }
+
+        if let Some(block) = current_block {
+            send(&sink, SourceEvent::BlockCompleted(block)).await?;
+        }
+        info!("Replay of {} finished: {} events", self.path.display(), replayed);
+        Ok(())
+    }
+
+    async fn decode_payload(&self, payload: &str) -> Result<OrderEvent, Error> {
+        let event: PangeaOrderEvent = serde_json::from_str(payload)?;
+        let timestamp = self
+            .block_timestamp(&event)
+            .await?
+            .ok_or(Error::BlockNotFound(event.block_number))?;
+        event.into_order_event(timestamp)
+    }
+}
+
+async fn send(sink: &mpsc::Sender<SourceEvent>, event: SourceEvent) -> Result<(), Error> {
+    sink.send(event).await.map_err(|_| Error::SinkClosed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pangea_recording.jsonl");
+
+    /// Проигрывает запись и возвращает отданные события в виде
+    /// `order <блок>:<log_index>` / `completed <блок>` и dead-letter записи.
+    async fn replay(last_block: Option<i64>) -> (Vec<String>, Vec<DeadLetter>) {
+        let dead_letters_path =
+            std::env::temp_dir().join(format!("replay-test-{}.jsonl", uuid::Uuid::new_v4()));
+        let dead_letters = Arc::new(DeadLetterQueue::new(&dead_letters_path));
+        let source = ReplaySource::new(RECORDING, ReplayPace::Full, Arc::clone(&dead_letters));
+        let (sink, mut received) = mpsc::channel(64);
+        source.run(last_block, sink).await.unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = received.try_recv() {
+            events.push(match event {
+                SourceEvent::Order(order) => {
+                    format!("order {}:{}", order.position.height, order.position.log_index)
+                }
+                SourceEvent::BlockCompleted(block) => format!("completed {}", block),
+                other => panic!("unexpected replay event {:?}", other),
+            });
+        }
+        let letters = dead_letters.list().unwrap();
+        let _ = std::fs::remove_file(&dead_letters_path);
+        (events, letters)
+    }
+
+    #[tokio::test]
+    async fn replays_recording_once_and_dead_letters_bad_lines() {
+        let (events, letters) = replay(None).await;
+
+        assert_eq!(
+            events,
+            [
+                "order 10:0",
+                "order 10:1",
+                "completed 10",
+                "order 11:0",
+                "completed 11",
+                "order 12:0",
+                "completed 12",
+            ]
+        );
+        // Обрезанная строка и событие неизвестного типа
+        assert_eq!(letters.len(), 2);
+        assert!(letters.iter().all(|letter| letter.indexer == Indexer::Replay));
+        assert_eq!(letters[0].block, None);
+        assert_eq!(letters[1].block, Some(11));
+        assert!(letters[1].payload.contains("\"block_timestamp\":1010"));
+    }
+
+    #[tokio::test]
+    async fn skips_blocks_emitted_before_restart() {
+        let (events, letters) = replay(Some(10)).await;
+
+        assert_eq!(
+            events,
+            ["order 11:0", "completed 11", "order 12:0", "completed 12"]
+        );
+        assert_eq!(letters.len(), 2);
+    }
+}
@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tokio::sync::mpsc;
+
+use crate::error::Error;
+use crate::indexer::dedup::EventKey;
+use crate::indexer::spot_order::{OrderType, SpotOrder};
+
+/// В JSON пишется так же, как в `as_str`. Прежние имена вариантов
+/// принимаются, чтобы читались уже записанные dead-letter файлы.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub enum Indexer {
+    #[serde(rename = "envio", alias = "Envio")]
+    Envio,
+    #[serde(rename = "subsquid", alias = "Subsquid")]
+    Subsquid,
+    #[serde(rename = "superchain", alias = "Pangea")]
+    Pangea,
+    #[serde(rename = "replay", alias = "Replay")]
+    Replay,
+    #[serde(rename = "fuel-node", alias = "FuelNode")]
+    FuelNode,
+}
+
+impl Indexer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Indexer::Envio => "envio",
+            Indexer::Subsquid => "subsquid",
+            Indexer::Pangea => "superchain",
+            Indexer::Replay => "replay",
+            Indexer::FuelNode => "fuel-node",
+        }
+    }
+
+    pub fn all() -> Vec<Indexer> {
+        vec![Indexer::Envio, Indexer::Subsquid, Indexer::Pangea, Indexer::Replay, Indexer::FuelNode]
+    }
+}
+
+impl FromStr for Indexer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "envio" => Ok(Indexer::Envio),
+            "subsquid" => Ok(Indexer::Subsquid),
+            "pangea" | "superchain" => Ok(Indexer::Pangea),
+            "replay" => Ok(Indexer::Replay),
+            "fuel-node" | "fuelnode" | "fuel" => Ok(Indexer::FuelNode),
+            other => Err(Error::ConfigError(format!("Unknown indexer: {}", other))),
+        }
+    }
+}
+
+/// Позиция события в цепи.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockPosition {
+    pub height: i64,
+    pub hash: String,
+    pub transaction_hash: String,
+    pub transaction_index: u64,
+    pub log_index: u64,
+}
+
+impl BlockPosition {
+    pub fn key(&self) -> EventKey {
+        EventKey {
+            transaction_hash: self.transaction_hash.clone(),
+            log_index: self.log_index,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderEventKind {
+    Open,
+    Trade,
+    Cancel,
+}
+
+/// Событие жизненного цикла ордера, не зависящее от бэкенда.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub position: BlockPosition,
+    /// Unix timestamp блока.
+    pub timestamp: i64,
+    pub market_id: String,
+    pub order_id: String,
+    pub kind: OrderEventKind,
+    pub order_type: Option<OrderType>,
+    pub asset: Option<String>,
+    pub amount: Option<u128>,
+    pub price: Option<u128>,
+    pub user: Option<String>,
+    /// Вторая сторона уже отданной сделки: книгу обновляет, в свечи не идет,
+    /// иначе объем сделки посчитается дважды.
+    #[serde(default)]
+    pub counterpart: bool,
+}
+
+/// Что источник отдает потребителю.
+#[derive(Debug)]
+pub enum SourceEvent {
+    Order(OrderEvent),
+    /// Полный набор активных ордеров одной стороны рынка.
+    /// Так работают бэкенды без событий жизненного цикла (Envio, Subsquid).
+    Snapshot {
+        market_id: String,
+        order_type: OrderType,
+        orders: Vec<SpotOrder>,
+    },
+    /// Все события блоков до `height` включительно уже отданы.
+    BlockCompleted(i64),
+    /// История догнана, дальше события идут в реальном времени.
+    CaughtUp,
+    /// Событие из dead-letter очереди. Приходит вне порядка блоков,
+    /// поэтому не участвует в детекте реорганизаций.
+    Redelivered(OrderEvent),
+    /// Источник перезапущен с блока `height` после реорганизации.
+    /// События до этого маркера относятся к брошенной ветке.
+    Rewound(i64),
+}
+
+/// Бэкенд индексатора, который пишет нормализованные события в `sink`.
+#[async_trait]
+pub trait EventSource: Send + Sync {
+    fn indexer(&self) -> Indexer;
+
+    /// Отдает события, начиная после блока `last_block`
+    /// (или с начала истории, если `None`). Возвращается только при ошибке
+    /// или когда потребитель закрыл канал.
+    async fn run(&self, last_block: Option<i64>, sink: mpsc::Sender<SourceEvent>) -> Result<(), Error>;
+
+    /// Разбирает сырой payload из dead-letter очереди.
+    /// Бэкенды без сырых событий повторную обработку не поддерживают.
+    async fn decode_payload(&self, _payload: &str) -> Result<OrderEvent, Error> {
+        Err(Error::ReprocessNotSupported(self.indexer().as_str().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexer_serializes_as_its_name() {
+        for indexer in Indexer::all() {
+            let json = serde_json::to_string(&indexer).unwrap();
+            assert_eq!(json, format!("\"{}\"", indexer.as_str()));
+            assert_eq!(serde_json::from_str::<Indexer>(&json).unwrap(), indexer);
+            assert_eq!(indexer.as_str().parse::<Indexer>().unwrap(), indexer);
+        }
+    }
+
+    #[test]
+    fn indexer_reads_old_variant_names() {
+        assert_eq!(serde_json::from_str::<Indexer>("\"Pangea\"").unwrap(), Indexer::Pangea);
+        assert_eq!(serde_json::from_str::<Indexer>("\"FuelNode\"").unwrap(), Indexer::FuelNode);
+    }
+}
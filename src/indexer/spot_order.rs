@@ -1,7 +1,8 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-use crate::error::{self, Error};
+use crate::error::Error;
 
 // NTD Adapt spark-sdk OrderType to that type
 #[derive(Debug, PartialEq, Eq, Clone, Copy, JsonSchema, Serialize, Deserialize)]
@@ -10,6 +11,18 @@ pub enum OrderType {
     Sell,
 }
 
+impl FromStr for OrderType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Buy" => Ok(OrderType::Buy),
+            "Sell" => Ok(OrderType::Sell),
+            a => Err(Error::UnknownOrderType(a.to_string())),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, JsonSchema, Serialize, Deserialize)]
 pub enum LimitType {
     FOK,
@@ -26,6 +39,37 @@ pub enum OrderStatus {
     Failed,
 }
 
+impl FromStr for OrderStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "New" => Ok(OrderStatus::New),
+            "PartiallyMatched" => Ok(OrderStatus::PartiallyMatched),
+            "Matched" | "Closed" => Ok(OrderStatus::Matched),
+            "Cancelled" | "Canceled" => Ok(OrderStatus::Cancelled),
+            "Failed" => Ok(OrderStatus::Failed),
+            a => Err(Error::UnknownOrderStatus(a.to_string())),
+        }
+    }
+}
+
+/// Статус ордера из снапшота индексатора. Индексаторы помечают все открытые
+/// ордера как `Active`; частично исполненный, как и в книге из событий,
+/// получает `PartiallyMatched` по остатку меньше начального объема.
+fn indexer_status(status: Option<&str>, amount: u128, initial_amount: Option<&str>) -> Result<OrderStatus, Error> {
+    match status {
+        None | Some("Active") => {
+            let initial_amount = initial_amount.map(str::parse::<u128>).transpose()?;
+            Ok(match initial_amount {
+                Some(initial_amount) if amount < initial_amount => OrderStatus::PartiallyMatched,
+                _ => OrderStatus::New,
+            })
+        }
+        Some(status) => status.parse(),
+    }
+}
+
 #[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, Eq)]
 pub struct SpotOrder {
     pub id: String,
@@ -93,6 +137,11 @@ impl SpotOrder {
         let price = intermediate.price.parse::<u128>()?;
         let timestamp =
             chrono::DateTime::parse_from_rfc3339(&intermediate.timestamp)?.timestamp() as u64;
+        let status = indexer_status(
+            intermediate.status.as_deref(),
+            amount,
+            intermediate.initial_amount.as_deref(),
+        )?;
 
         Ok(SpotOrder {
             id: intermediate.id,
@@ -102,7 +151,7 @@ impl SpotOrder {
             price,
             timestamp,
             order_type: intermediate.order_type,
-            status: Some(OrderStatus::New),
+            status: Some(status),
         })
     }
 
@@ -111,11 +160,8 @@ impl SpotOrder {
         let price = order.price.parse::<u128>()?;
         let timestamp = chrono::DateTime::parse_from_rfc3339(&order.timestamp)?.timestamp() as u64;
 
-        let order_type = match order.order_type.as_str() {
-            "Buy" => OrderType::Buy,
-            "Sell" => OrderType::Sell,
-            a => return Err(error::Error::UnknownOrderType(a.to_string())),
-        };
+        let order_type = order.order_type.parse()?;
+        let status = indexer_status(Some(&order.status), amount, Some(&order.initial_amount))?;
 
         Ok(SpotOrder {
             id: order.id,
@@ -125,12 +171,13 @@ impl SpotOrder {
             price,
             timestamp,
             order_type,
-            status: Some(OrderStatus::New),
+            status: Some(status),
         })
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct OrderPayloadSubsquid {
     pub active_buy_orders: Option<Vec<SubsquidOrder>>,
     pub active_sell_orders: Option<Vec<SubsquidOrder>>,
@@ -144,15 +191,3 @@ pub struct OrderPayloadEnvio {
     #[serde(rename = "ActiveSellOrder")]
     pub active_sell_order: Option<Vec<SpotOrderEnvio>>,
 }
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct DataPayloadEnvio {
-    pub data: OrderPayloadEnvio,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct WebSocketResponseEnvio {
-    pub r#type: String,
-    pub id: Option<String>,
-    pub payload: Option<DataPayloadEnvio>,
-}
@@ -0,0 +1,46 @@
+use crate::error::Error;
+use crate::indexer::graphql_ws::{GraphqlSnapshotSource, Protocol, SnapshotBackend};
+use crate::indexer::source::Indexer;
+use crate::indexer::spot_order::{OrderPayloadSubsquid, OrderType, SpotOrder, SubsquidOrder};
+
+const ORDER_FIELDS: &str = "id asset amount price timestamp orderType user status initialAmount";
+
+/// Subsquid (протокол `graphql-transport-ws`), снимки `activeBuyOrders`/`activeSellOrders`.
+pub struct Subsquid;
+
+pub type SubsquidSource = GraphqlSnapshotSource<Subsquid>;
+
+impl SnapshotBackend for Subsquid {
+    type Order = SubsquidOrder;
+    type Data = OrderPayloadSubsquid;
+
+    const INDEXER: Indexer = Indexer::Subsquid;
+    const NAME: &'static str = "Subsquid";
+    const PROTOCOL: Protocol = Protocol::GraphqlTransportWs;
+
+    fn subscription(market_id: &str, order_type: OrderType) -> String {
+        let field = match order_type {
+            OrderType::Buy => "activeBuyOrders",
+            OrderType::Sell => "activeSellOrders",
+        };
+        format!(
+            "subscription {{ {}(where: {{market_eq: \"{}\"}}) {{ {} }} }}",
+            field, market_id, ORDER_FIELDS
+        )
+    }
+
+    fn orders(data: OrderPayloadSubsquid, order_type: OrderType) -> Option<Vec<SubsquidOrder>> {
+        match order_type {
+            OrderType::Buy => data.active_buy_orders,
+            OrderType::Sell => data.active_sell_orders,
+        }
+    }
+
+    fn order_id(order: &SubsquidOrder) -> &str {
+        &order.id
+    }
+
+    fn convert(order: SubsquidOrder) -> Result<SpotOrder, Error> {
+        SpotOrder::from_indexer_subsquid(order)
+    }
+}
@@ -0,0 +1,377 @@
+use log::{error, info, warn};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+use crate::config::env::ev;
+use crate::error::Error;
+use crate::indexer::source::{EventSource, Indexer, SourceEvent};
+
+const DEFAULT_MAX_RETRIES: u32 = 10;
+/// Если источник проработал столько без падения, бюджет перезапусков восстанавливается.
+const DEFAULT_HEALTHY_AFTER_SECS: u64 = 600;
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    /// Догоняет историю.
+    Syncing,
+    /// Получает новые блоки в реальном времени.
+    Live,
+    /// Упал и ждет перезапуска.
+    Restarting,
+    /// Исчерпал бюджет перезапусков, данные больше не обновляются.
+    Failed,
+    /// Источник завершился сам (например, закончилась запись для replay).
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TaskStatus {
+    pub indexer: Indexer,
+    pub state: TaskState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    /// Последний блок, полностью примененный к сторам.
+    pub last_block: Option<i64>,
+    pub updated_at: i64,
+}
+
+/// Состояние одного индексатора. Источник меняет его через супервизор,
+/// потребитель — по мере применения блоков.
+pub struct IndexerStatus {
+    status: Mutex<TaskStatus>,
+}
+
+impl IndexerStatus {
+    pub fn new(indexer: Indexer, last_block: Option<i64>) -> Self {
+        Self {
+            status: Mutex::new(TaskStatus {
+                indexer,
+                state: TaskState::Syncing,
+                restarts: 0,
+                last_error: None,
+                last_block,
+                updated_at: chrono::Utc::now().timestamp(),
+            }),
+        }
+    }
+
+    pub fn get(&self) -> TaskStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn last_block(&self) -> Option<i64> {
+        self.status.lock().unwrap().last_block
+    }
+
+    pub fn set_state(&self, state: TaskState) {
+        self.update(|status| status.state = state);
+    }
+
+    /// Переводит в `Live`, если индексатор сейчас синхронизируется.
+    pub fn mark_live(&self) {
+        self.update(|status| {
+            if status.state == TaskState::Syncing {
+                status.state = TaskState::Live;
+            }
+        });
+    }
+
+    /// Источник завершился. `Failed` не перетирается: канал мог закрыться
+    /// из-за упавшего потребителя.
+    pub fn mark_stopped(&self) {
+        self.update(|status| {
+            if status.state != TaskState::Failed {
+                status.state = TaskState::Stopped;
+            }
+        });
+    }
+
+    /// Индексатор больше не обновляет данные, например упал потребитель событий.
+    pub fn fail(&self, error: String) {
+        self.record_failure(TaskState::Failed, error);
+    }
+
+    pub fn set_last_block(&self, height: i64) {
+        self.update(|status| status.last_block = Some(height));
+    }
+
+    fn record_failure(&self, state: TaskState, error: String) {
+        self.update(|status| {
+            status.state = state;
+            status.last_error = Some(error);
+            if state == TaskState::Restarting {
+                status.restarts += 1;
+            }
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut TaskStatus)) {
+        let mut status = self.status.lock().unwrap();
+        f(&mut status);
+        status.updated_at = chrono::Utc::now().timestamp();
+    }
+}
+
+/// Состояния всех запущенных индексаторов для API.
+pub struct IndexerStatuses {
+    statuses: HashMap<Indexer, Arc<IndexerStatus>>,
+}
+
+impl IndexerStatuses {
+    pub fn new(statuses: HashMap<Indexer, Arc<IndexerStatus>>) -> Self {
+        Self { statuses }
+    }
+
+    pub fn all(&self) -> Vec<TaskStatus> {
+        let mut statuses: Vec<TaskStatus> = self.statuses.values().map(|s| s.get()).collect();
+        statuses.sort_by_key(|s| s.indexer.as_str());
+        statuses
+    }
+}
+
+/// Сколько раз и как быстро перезапускать упавший источник.
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    pub max_retries: u32,
+    pub healthy_after: Duration,
+}
+
+impl RestartPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            max_retries: ev("SUPERVISOR_MAX_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+            healthy_after: Duration::from_secs(
+                ev("SUPERVISOR_HEALTHY_AFTER_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_HEALTHY_AFTER_SECS),
+            ),
+        }
+    }
+}
+
+/// Запускает источник и перезапускает его после ошибок с экспоненциальной
+/// паузой. После перезапуска источник продолжает с последнего примененного блока,
+/// повторно отданные события отсеет дедупликация потребителя.
+/// По высоте из `rewinds` источник сразу перезапускается с нее: так после
+/// реорганизации заново читаются блоки канонической ветки.
+pub async fn supervise(
+    source: Arc<dyn EventSource>,
+    sink: mpsc::Sender<SourceEvent>,
+    status: Arc<IndexerStatus>,
+    policy: RestartPolicy,
+    mut rewinds: mpsc::UnboundedReceiver<i64>,
+) {
+    let indexer = source.indexer();
+    let mut failures = 0;
+    let mut retry_delay = Duration::from_secs(1);
+
+    loop {
+        let started = Instant::now();
+        let result = tokio::select! {
+            result = source.run(status.last_block(), sink.clone()) => result,
+            Some(height) = rewinds.recv() => {
+                info!("Indexer {:?} rewinding to block {}", indexer, height);
+                if sink.send(SourceEvent::Rewound(height)).await.is_err() {
+                    status.mark_stopped();
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let e = match result {
+            Ok(()) | Err(Error::SinkClosed) => {
+                info!("Indexer {:?} stopped", indexer);
+                status.mark_stopped();
+                return;
+            }
+            Err(e) => e,
+        };
+
+        if started.elapsed() >= policy.healthy_after {
+            failures = 0;
+            retry_delay = Duration::from_secs(1);
+        }
+        failures += 1;
+        if failures > policy.max_retries {
+            error!(
+                "Indexer {:?} failed {} times in a row, giving up: {}",
+                indexer, failures, e
+            );
+            status.record_failure(
+                TaskState::Failed,
+                format!("{}: {}", Error::MaxRetriesExceeded, e),
+            );
+            return;
+        }
+
+        warn!(
+            "Indexer {:?} failed: {}. Restarting in {} seconds (attempt {}/{})",
+            indexer,
+            e,
+            retry_delay.as_secs(),
+            failures,
+            policy.max_retries
+        );
+        status.record_failure(TaskState::Restarting, e.to_string());
+        sleep(retry_delay).await;
+        retry_delay = (retry_delay * 2).min(MAX_RESTART_DELAY);
+        status.set_state(TaskState::Syncing);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::markets::Markets;
+    use crate::indexer::order_event_handler::OrderEventHandler;
+    use crate::indexer::source::{BlockPosition, OrderEvent, OrderEventKind};
+    use crate::indexer::spot_order::OrderType;
+    use crate::storage::candles::InMemoryCandleStore;
+    use crate::storage::checkpoint::CheckpointStore;
+    use crate::storage::order_book::OrderBooks;
+    use crate::storage::retention::Retention;
+    use crate::storage::timeframes::{Timeframes, BASE_INTERVAL};
+    use async_trait::async_trait;
+    use tokio::time::timeout;
+
+    const MARKET: &str = "0x0000000000000000000000000000000000000000000000000000000000000001";
+
+    fn event(height: i64, hash: &str, kind: OrderEventKind, amount: u128) -> OrderEvent {
+        OrderEvent {
+            position: BlockPosition {
+                height,
+                hash: hash.to_string(),
+                transaction_hash: format!("{}-tx", hash),
+                transaction_index: 0,
+                log_index: 0,
+            },
+            timestamp: 60,
+            market_id: MARKET.to_string(),
+            order_id: "order".to_string(),
+            kind,
+            order_type: Some(OrderType::Sell),
+            asset: None,
+            amount: Some(amount),
+            price: Some(100),
+            user: None,
+            counterpart: false,
+        }
+    }
+
+    /// Первый запуск отдает ветку A и затем блок 2 ветки B, следующие —
+    /// каноническую ветку B после `last_block`.
+    struct ForkingSource {
+        starts: Mutex<Vec<Option<i64>>>,
+    }
+
+    #[async_trait]
+    impl EventSource for ForkingSource {
+        fn indexer(&self) -> Indexer {
+            Indexer::Replay
+        }
+
+        async fn run(&self, last_block: Option<i64>, sink: mpsc::Sender<SourceEvent>) -> Result<(), Error> {
+            let first = {
+                let mut starts = self.starts.lock().unwrap();
+                starts.push(last_block);
+                starts.len() == 1
+            };
+            let events = if first {
+                vec![
+                    SourceEvent::Order(event(1, "h1", OrderEventKind::Open, 10)),
+                    SourceEvent::BlockCompleted(1),
+                    SourceEvent::Order(event(2, "a2", OrderEventKind::Trade, 4)),
+                    SourceEvent::BlockCompleted(2),
+                    SourceEvent::Order(event(2, "b2", OrderEventKind::Trade, 7)),
+                ]
+            } else {
+                vec![
+                    SourceEvent::Order(event(2, "b2", OrderEventKind::Trade, 7)),
+                    SourceEvent::BlockCompleted(2),
+                ]
+            };
+            for event in events {
+                sink.send(event).await.map_err(|_| Error::SinkClosed)?;
+            }
+            std::future::pending::<()>().await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reorg_rewinds_source_and_reapplies_canonical_blocks() {
+        let config = format!("[[markets]]\nid = \"{}\"\nsymbol = \"A/B\"", MARKET);
+        let markets = Arc::new(Markets::from_toml(&config).unwrap());
+        let timeframes = Arc::new(Timeframes::from_env(Arc::new(InMemoryCandleStore::new(
+            Retention::default(),
+        ))));
+        let order_books = Arc::new(OrderBooks::new());
+        let status = Arc::new(IndexerStatus::new(Indexer::Replay, None));
+        let checkpoint =
+            std::env::temp_dir().join(format!("reorg-test-{}.json", uuid::Uuid::new_v4()));
+        let (rewind_requests, rewinds) = mpsc::unbounded_channel();
+        let handler = OrderEventHandler::new(
+            Some(Arc::clone(&timeframes)),
+            Arc::clone(&order_books),
+            markets,
+            CheckpointStore::new(checkpoint, Duration::from_secs(3600)),
+            100,
+            1_000,
+        )
+        .with_status(Arc::clone(&status))
+        .with_rewinds(rewind_requests);
+
+        let source = Arc::new(ForkingSource {
+            starts: Mutex::new(vec![]),
+        });
+        let (sink, events) = mpsc::channel(100);
+        let consumer = tokio::spawn(async move { handler.consume(events).await });
+        let supervisor = tokio::spawn(supervise(
+            Arc::clone(&source) as Arc<dyn EventSource>,
+            sink,
+            Arc::clone(&status),
+            RestartPolicy {
+                max_retries: 0,
+                healthy_after: Duration::from_secs(600),
+            },
+            rewinds,
+        ));
+
+        let remaining = || {
+            order_books
+                .get("A/B")
+                .and_then(|book| book.find_order("order"))
+                .map(|order| order.amount)
+        };
+        timeout(Duration::from_secs(5), async {
+            while remaining() != Some(3) || status.last_block() != Some(2) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("canonical block was not re-applied");
+
+        assert_eq!(*source.starts.lock().unwrap(), vec![None, Some(1)]);
+        let volume: u128 = timeframes
+            .get_candles_in_time_range_secs("A/B", BASE_INTERVAL, 0, 120)
+            .iter()
+            .map(|c| c.volume)
+            .sum();
+        assert_eq!(volume, 7);
+
+        supervisor.abort();
+        consumer.abort();
+    }
+}
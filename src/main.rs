@@ -1,11 +1,15 @@
+use config::chain::Chain;
 use config::env::ev;
+use config::market_metadata::load_market_metadata;
+use config::markets::Markets;
 use error::Error;
 use futures_util::future::FutureExt;
 use futures_util::future::{join_all, select};
-use indexer::pangea::initialize_pangea_indexer;
-use storage::candles::CandleStore;
+use indexer::consistency::{initialize_consistency_checker, ConsistencyChecker};
+use indexer::pipeline::{initialize_indexers, Indexers};
+use storage::candles::open_candle_store;
+use storage::timeframes::Timeframes;
 use std::sync::Arc;
-use storage::order_book::OrderBook;
 use tokio::signal;
 use web::server::rocket;
 
@@ -14,21 +18,31 @@ pub mod error;
 pub mod indexer;
 pub mod storage;
 pub mod web;
+#[cfg(test)]
+mod test_node;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     dotenv::dotenv().ok();
     env_logger::init();
 
-    let order_book = Arc::new(OrderBook::new());
-    let candle_store = Arc::new(CandleStore::new());
+    let chain = Chain::from_env()?;
+    let mut markets = Markets::load()?;
+    if let Some(chain) = chain {
+        load_market_metadata(&mut markets, chain).await?;
+    }
+    let markets = Arc::new(markets);
+    let timeframes = Arc::new(Timeframes::from_env(Arc::from(open_candle_store()?)));
     let mut tasks = vec![];
 
-    initialize_pangea_indexer(&mut tasks, Arc::clone(&candle_store)).await?;
+    let indexers = initialize_indexers(&mut tasks, Arc::clone(&timeframes),
+        Arc::clone(&markets), chain).await?;
+    let consistency = initialize_consistency_checker(&mut tasks, Arc::clone(&indexers.order_books),
+        Arc::clone(&markets));
 
     let port = ev("SERVER_PORT")?.parse()?;
-    let rocket_task = tokio::spawn(run_rocket_server(port, Arc::clone(&order_book),
-        Arc::clone(&candle_store)
+    let rocket_task = tokio::spawn(run_rocket_server(port, indexers,
+        Arc::clone(&timeframes), Arc::clone(&markets), consistency
     ));
     tasks.push(rocket_task);
 
@@ -48,9 +62,10 @@ async fn main() -> Result<(), Error> {
     Ok(())
 }
 
-async fn run_rocket_server(port: u16, order_book: Arc<OrderBook>,
-    candle_store: Arc<CandleStore>
+async fn run_rocket_server(port: u16, indexers: Indexers,
+    timeframes: Arc<Timeframes>, markets: Arc<Markets>,
+    consistency: Arc<ConsistencyChecker>
 ) {
-    let rocket = rocket(port, order_book, candle_store);
+    let rocket = rocket(port, indexers, timeframes, markets, consistency);
     let _ = rocket.launch().await;
 }
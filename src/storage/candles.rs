@@ -1,208 +1,741 @@
 use chrono::{DateTime, Utc};
-use log::info;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
 
-/// Представление одной свечи (OHLCV).
-#[derive(Debug, Clone)]
+use crate::config::env::ev;
+use crate::error::Error;
+use crate::storage::retention::Retention;
+use crate::storage::sled_candles::SledCandleStore;
+
+/// Путь к базе свечей по умолчанию для `CANDLE_STORE=sled` и `tiered`.
+const DEFAULT_CANDLE_DB_PATH: &str = "candles.db";
+
+/// Представление одной свечи (OHLCV) в сырых единицах контракта: цены
+/// с `price_decimals` знаками, объем с `base_decimals`. Целые значения
+/// не теряют точность и суммируются без накопления ошибки.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Candle {
-    pub open: f64,
-    pub high: f64,
-    pub low: f64,
-    pub close: f64,
-    pub volume: f64,
+    pub open: u128,
+    pub high: u128,
+    pub low: u128,
+    pub close: u128,
+    pub volume: u128,
     pub timestamp: DateTime<Utc>, // Время начала интервала свечи
+    /// Время первой и последней сделки, `None` у дозаполненных свечей.
+    /// По ним поздняя сделка попадает в open или close только по своему времени.
+    #[serde(default)]
+    pub first_trade: Option<i64>,
+    #[serde(default)]
+    pub last_trade: Option<i64>,
 }
 
-/// Основной стор для хранения и управления свечами.
-#[derive(Debug)]
-pub struct CandleStore {
-    // candles: symbol -> interval -> Vec<Candle>
-    candles: RwLock<HashMap<String, HashMap<u64, Vec<Candle>>>>,
-}
-
-impl CandleStore {
-    /// Создает новый пустой `CandleStore`.
-    pub fn new() -> Self {
+impl Candle {
+    /// Свеча без сделок с ценой `price`.
+    pub(crate) fn flat(period_start: i64, price: u128, volume: u128) -> Self {
         Self {
-            candles: RwLock::new(HashMap::new()),
-        }
-    }
-
-    pub fn add_price(&self, symbol: &str, interval: u64, price: f64, volume: f64, event_time: i64) {
-        let mut candles = self.candles.write().unwrap();
-
-        // Достаем свечи для указанного символа и интервала
-        let symbol_candles = candles.entry(symbol.to_string()).or_insert_with(HashMap::new);
-        let candle_list = symbol_candles.entry(interval).or_insert_with(Vec::new);
-
-        // Рассчитываем начало периода на основе времени события
-        let period_start = event_time - (event_time % interval as i64);
-
-        // Проверяем последнюю свечу
-        if let Some(last_candle) = candle_list.last() {
-            let last_timestamp = last_candle.timestamp.timestamp();
-            let last_close = last_candle.close;
-
-            if last_timestamp == period_start {
-                // Обновляем текущую свечу
-                if let Some(last_candle) = candle_list.last_mut() {
-                    last_candle.high = last_candle.high.max(price);
-                    last_candle.low = last_candle.low.min(price);
-                    last_candle.close = price;
-                    last_candle.volume += volume;
-                }
-                return;
-            } else if last_timestamp < period_start {
-                // Добавляем пропущенные свечи
-                let mut missing_start = last_timestamp + interval as i64;
-                while missing_start < period_start {
-                    let empty_candle = Candle {
-                        open: last_close,
-                        high: last_close,
-                        low: last_close,
-                        close: last_close,
-                        volume: 0.0,
-                        timestamp: DateTime::<Utc>::from_utc(
-                            chrono::NaiveDateTime::from_timestamp(missing_start, 0),
-                            Utc,
-                        ),
-                    };
-                    candle_list.push(empty_candle);
-                    missing_start += interval as i64;
-                }
-            }
-        }
-
-        // Создаем новую свечу
-        let new_candle = Candle {
             open: price,
             high: price,
             low: price,
             close: price,
             volume,
-            timestamp: DateTime::<Utc>::from_utc(
-                chrono::NaiveDateTime::from_timestamp(period_start, 0),
-                Utc,
-            ),
-        };
-        candle_list.push(new_candle);
+            timestamp: DateTime::from_timestamp(period_start, 0).unwrap_or_default(),
+            first_trade: None,
+            last_trade: None,
+        }
+    }
+
+    /// Свеча из одной сделки.
+    pub(crate) fn traded(period_start: i64, price: u128, volume: u128, trade_time: i64) -> Self {
+        Self {
+            first_trade: Some(trade_time),
+            last_trade: Some(trade_time),
+            ..Self::flat(period_start, price, volume)
+        }
+    }
 
-        // Ограничиваем количество хранимых свечей
-        const MAX_CANDLES: usize = 1000;
-        if candle_list.len() > MAX_CANDLES {
-            candle_list.drain(0..(candle_list.len() - MAX_CANDLES));
+    pub(crate) fn has_trades(&self) -> bool {
+        self.last_trade.is_some()
+    }
+
+    /// Добавляет сделку в свечу с учетом ее времени, а не порядка прихода.
+    fn add_trade(&mut self, price: u128, volume: u128, trade_time: i64) {
+        let (Some(first), Some(last)) = (self.first_trade, self.last_trade) else {
+            // Дозаполненная свеча получает первую настоящую сделку
+            *self = Self::traded(self.timestamp.timestamp(), price, volume, trade_time);
+            return;
+        };
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.volume += volume;
+        if trade_time < first {
+            self.open = price;
+            self.first_trade = Some(trade_time);
         }
+        if trade_time >= last {
+            self.close = price;
+            self.last_trade = Some(trade_time);
+        }
+    }
+
+    /// Дописывает следующую по времени свечу: open остается, close берется из `next`.
+    pub(crate) fn extend(&mut self, next: &Candle) {
+        self.high = self.high.max(next.high);
+        self.low = self.low.min(next.low);
+        self.close = next.close;
+        self.volume += next.volume;
+        self.first_trade = self.first_trade.or(next.first_trade);
+        self.last_trade = next.last_trade.or(self.last_trade);
     }
+}
+
+/// Состояние серии до `add_price`, по нему изменение можно откатить.
+#[derive(Debug, Clone)]
+pub struct CandleUndo {
+    symbol: String,
+    interval: u64,
+    /// Прежние значения измененных свечей в порядке изменения, `None` если свечи не было.
+    previous: Vec<(i64, Option<Candle>)>,
+    /// Изменение архива, если сделка ушла в него. Откатывается после `previous`.
+    archived: Option<Box<CandleUndo>>,
+}
+
+/// Снимок всех свечей: symbol -> interval -> Vec<Candle>.
+pub type CandleSnapshot = HashMap<String, HashMap<u64, Vec<Candle>>>;
 
-    /// Получает последние `count` свечей для заданного символа и интервала.
-    pub fn get_candles(
+/// Хранилище свечей. Бэкенды встроенные, поэтому методы синхронные.
+pub trait CandleStore: Send + Sync {
+    /// Добавляет сделку в свечу периода `event_time`, дозаполняя пропущенные периоды.
+    fn add_price(
         &self,
         symbol: &str,
         interval: u64,
-        count: usize,
-    ) -> Vec<Candle> {
-        let candles = self.candles.read().unwrap();
-        if let Some(symbol_candles) = candles.get(symbol) {
-            if let Some(interval_candles) = symbol_candles.get(&interval) {
-                return interval_candles.iter().rev().take(count).cloned().collect();
+        price: u128,
+        volume: u128,
+        event_time: i64,
+    ) -> Result<CandleUndo, Error>;
+
+    /// Откатывает изменение, сделанное `add_price`.
+    /// Откаты должны применяться в порядке, обратном добавлению.
+    fn revert(&self, undo: CandleUndo) -> Result<(), Error>;
+
+    /// Свечи с началом в `[from, to]` (секунды), по возрастанию времени.
+    fn get_candles_in_time_range_secs(&self, symbol: &str, interval: u64, from: u64, to: u64) -> Vec<Candle>;
+
+    fn get_min_max_timestamps(&self) -> Option<(i64, i64)>;
+
+    /// Вызывается при сохранении чекпоинта. Возвращает свечи, которые нужно
+    /// положить в чекпоинт; персистентный бэкенд возвращает пустой снимок,
+    /// его изменения пишет `commit`.
+    fn snapshot(&self) -> Result<CandleSnapshot, Error>;
+
+    /// Пишет накопленные изменения на диск одной транзакцией с сериализованным
+    /// чекпоинтом. `false` — бэкенд чекпоинты не хранит, их пишут в файл.
+    fn commit(&self, _checkpoint: &[u8]) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// Чекпоинт, записанный последним `commit`.
+    fn committed_checkpoint(&self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(None)
+    }
+
+    /// Восстанавливает свечи из чекпоинта.
+    fn restore(&self, snapshot: CandleSnapshot) -> Result<(), Error>;
+
+    /// Удаляет все свечи. Нужно, когда история строится заново без чекпоинта.
+    fn clear(&self) -> Result<(), Error>;
+
+    /// Кладет готовые свечи серии, заменяя свечи с тем же временем начала.
+    /// Так стор в памяти переносит вытесненные свечи в персистентный.
+    fn put_candles(&self, symbol: &str, interval: u64, candles: Vec<Candle>) -> Result<(), Error>;
+}
+
+/// Открывает хранилище из `CANDLE_STORE`: `memory` (по умолчанию), `sled`
+/// или `tiered` — память, из которой вытесненные свечи уходят в sled.
+/// Сколько свечей держать в памяти, задает `CANDLE_RETENTION`.
+pub fn open_candle_store() -> Result<Box<dyn CandleStore>, Error> {
+    let kind = ev("CANDLE_STORE").unwrap_or_else(|_| "memory".to_string());
+    let db_path = || ev("CANDLE_DB_PATH").unwrap_or_else(|_| DEFAULT_CANDLE_DB_PATH.to_string());
+    match kind.to_lowercase().as_str() {
+        "memory" => Ok(Box::new(InMemoryCandleStore::new(Retention::from_env()?))),
+        "sled" => {
+            let path = db_path();
+            info!("Opening candle database at {}", path);
+            Ok(Box::new(SledCandleStore::open(path)?))
+        }
+        "tiered" => {
+            let path = db_path();
+            info!("Opening candle archive at {}", path);
+            let archive = SledCandleStore::open(path)?;
+            Ok(Box::new(
+                InMemoryCandleStore::new(Retention::from_env()?)
+                    .with_archive(Box::new(archive)),
+            ))
+        }
+        other => Err(Error::ConfigError(format!("Unknown CANDLE_STORE: {}", other))),
+    }
+}
+
+/// Доступ к одной серии свечей (symbol, interval). Через него логика
+/// агрегации общая для всех бэкендов.
+pub(crate) trait CandleSeries {
+    fn last(&mut self) -> Result<Option<Candle>, Error>;
+    fn get(&mut self, period_start: i64) -> Result<Option<Candle>, Error>;
+    /// Вставляет или заменяет свечу с тем же временем начала.
+    fn put(&mut self, candle: Candle) -> Result<(), Error>;
+    fn remove(&mut self, period_start: i64) -> Result<(), Error>;
+}
+
+/// Добавляет сделку в серию. Серия остается отсортированной, по одной свече
+/// на период: сделка новее последней свечи дозаполняет пропуск до себя,
+/// поздняя сделка вливается в свечу своего периода, после чего пересчитываются
+/// следующие за ней дозаполненные свечи.
+pub(crate) fn apply_price(
+    series: &mut impl CandleSeries,
+    symbol: &str,
+    interval: u64,
+    price: u128,
+    volume: u128,
+    event_time: i64,
+) -> Result<CandleUndo, Error> {
+    let step = interval as i64;
+    // Рассчитываем начало периода на основе времени события
+    let period_start = event_time - event_time.rem_euclid(step);
+    let mut undo = CandleUndo::new(symbol, interval);
+
+    let last = series.last()?;
+    let last_start = last.as_ref().map(|c| c.timestamp.timestamp());
+    if let (Some(last), Some(last_start)) = (&last, last_start) {
+        if last_start < period_start {
+            // Добавляем пропущенные свечи
+            let mut missing_start = last_start + step;
+            while missing_start < period_start {
+                undo.replace(series, Candle::flat(missing_start, last.close, 0), None)?;
+                missing_start += step;
             }
+            let candle = Candle::traded(period_start, price, volume, event_time);
+            undo.replace(series, candle, None)?;
+            return Ok(undo);
         }
-        vec![]
     }
 
-    pub fn get_candles_in_time_range_mils(
+    // Свеча текущего периода или поздняя сделка внутри серии
+    let previous = series.get(period_start)?;
+    let candle = match previous.clone() {
+        Some(mut candle) => {
+            candle.add_trade(price, volume, event_time);
+            candle
+        }
+        None => Candle::traded(period_start, price, volume, event_time),
+    };
+    let close = candle.close;
+    undo.replace(series, candle, previous)?;
+
+    // Дозаполненные свечи после нее до следующей свечи со сделками
+    // повторяют новую цену закрытия
+    if let Some(last_start) = last_start {
+        refill_flat(series, &mut undo, period_start + step, last_start, step, close)?;
+    }
+    Ok(undo)
+}
+
+/// Переписывает дозаполненные свечи с `from` по `until` на цену `close`,
+/// пока не встретится свеча со сделками.
+fn refill_flat(
+    series: &mut impl CandleSeries,
+    undo: &mut CandleUndo,
+    from: i64,
+    until: i64,
+    step: i64,
+    close: u128,
+) -> Result<(), Error> {
+    let mut start = from;
+    while start <= until {
+        let next = series.get(start)?;
+        match &next {
+            Some(candle) if candle.has_trades() => break,
+            Some(candle) if candle.close == close => {}
+            _ => undo.replace(series, Candle::flat(start, close, 0), next)?,
+        }
+        start += step;
+    }
+    Ok(())
+}
+
+pub(crate) fn apply_revert(series: &mut impl CandleSeries, undo: CandleUndo) -> Result<(), Error> {
+    for (period_start, previous) in undo.previous.into_iter().rev() {
+        match previous {
+            Some(previous) => series.put(previous)?,
+            None => series.remove(period_start)?,
+        }
+    }
+    Ok(())
+}
+
+impl CandleUndo {
+    fn new(symbol: &str, interval: u64) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            interval,
+            previous: Vec::new(),
+            archived: None,
+        }
+    }
+
+    pub(crate) fn series(&self) -> (&str, u64) {
+        (&self.symbol, self.interval)
+    }
+
+    /// Начало самой ранней свечи, затронутой изменением, включая дозаполненные.
+    pub(crate) fn affected_from(&self) -> i64 {
+        self.previous
+            .iter()
+            .map(|(period_start, _)| *period_start)
+            .chain(self.archived.as_ref().map(|archived| archived.affected_from()))
+            .min()
+            .unwrap_or(i64::MAX)
+    }
+
+    /// Пишет свечу в серию, запоминая прежнее значение.
+    fn replace(
+        &mut self,
+        series: &mut impl CandleSeries,
+        candle: Candle,
+        previous: Option<Candle>,
+    ) -> Result<(), Error> {
+        self.previous.push((candle.timestamp.timestamp(), previous));
+        series.put(candle)
+    }
+}
+
+pub(crate) type Series = BTreeMap<i64, Candle>;
+
+impl CandleSeries for Series {
+    fn last(&mut self) -> Result<Option<Candle>, Error> {
+        Ok(self.values().next_back().cloned())
+    }
+
+    fn get(&mut self, period_start: i64) -> Result<Option<Candle>, Error> {
+        Ok(BTreeMap::get(self, &period_start).cloned())
+    }
+
+    fn put(&mut self, candle: Candle) -> Result<(), Error> {
+        self.insert(candle.timestamp.timestamp(), candle);
+        Ok(())
+    }
+
+    fn remove(&mut self, period_start: i64) -> Result<(), Error> {
+        BTreeMap::remove(self, &period_start);
+        Ok(())
+    }
+}
+
+/// Стор свечей в памяти процесса: symbol -> interval -> серия по времени начала.
+/// Свечи сверх политики хранения удаляются или, если задан архив, переносятся
+/// в него; чтение старше памяти тогда идет из архива.
+pub struct InMemoryCandleStore {
+    candles: RwLock<HashMap<String, HashMap<u64, Series>>>,
+    retention: Retention,
+    archive: Option<Box<dyn CandleStore>>,
+}
+
+impl InMemoryCandleStore {
+    /// Создает новый пустой стор.
+    pub fn new(retention: Retention) -> Self {
+        Self {
+            candles: RwLock::new(HashMap::new()),
+            retention,
+            archive: None,
+        }
+    }
+
+    pub fn with_archive(mut self, archive: Box<dyn CandleStore>) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Кладет в архив сделку старше всех свечей серии в памяти. Дозаполненные
+    /// свечи в памяти после границы продолжают цену закрытия архива.
+    #[allow(clippy::too_many_arguments)]
+    fn add_archived(
         &self,
+        archive: &dyn CandleStore,
+        series: &mut Series,
         symbol: &str,
         interval: u64,
-        from: u64,
-        to: u64,
-    ) -> Vec<Candle> {
+        price: u128,
+        volume: u128,
+        event_time: i64,
+    ) -> Result<CandleUndo, Error> {
+        let archived = archive.add_price(symbol, interval, price, volume, event_time)?;
+        let mut undo = CandleUndo::new(symbol, interval);
+        let (Some(&oldest), Some(&newest)) = (series.keys().next(), series.keys().next_back()) else {
+            undo.archived = Some(Box::new(archived));
+            return Ok(undo);
+        };
+        let boundary = archive
+            .get_candles_in_time_range_secs(
+                symbol,
+                interval,
+                archived.affected_from().max(0) as u64,
+                (oldest - 1) as u64,
+            )
+            .pop();
+        if let Some(boundary) = boundary {
+            refill_flat(series, &mut undo, oldest, newest, interval as i64, boundary.close)?;
+        }
+        undo.archived = Some(Box::new(archived));
+        Ok(undo)
+    }
+
+    /// Время начала самой старой свечи серии в памяти.
+    fn oldest_in_memory(&self, symbol: &str, interval: u64) -> Option<i64> {
         let candles = self.candles.read().unwrap();
-        if let Some(interval_candles) = candles
+        candles
             .get(symbol)
             .and_then(|interval_map| interval_map.get(&interval))
-        {
-            let filtered: Vec<Candle> = interval_candles
-                .iter()
-                .filter(|c| {
-                    let timestamp = c.timestamp.timestamp() as u64;
-                    let result = timestamp >= from && timestamp <= to;
-                    info!(
-                        "Filtering candle: {:?}, timestamp: {}, result: {}",
-                        c, timestamp, result
-                    );
-                    result
-                })
-                .cloned()
-                .collect();
-
-            info!("Filtered candles: {:?}", filtered);
-            filtered
-        } else {
-            info!(
-                "No candles found for symbol: {}, interval: {}, from: {}, to: {}",
-                symbol, interval, from, to
-            );
-            vec![]
-        }
-    }
-
-    pub fn get_candles_in_time_range_secs(
+            .and_then(|series| series.keys().next().copied())
+    }
+}
+
+impl CandleStore for InMemoryCandleStore {
+    fn add_price(
         &self,
         symbol: &str,
         interval: u64,
-        from: u64,
-        to: u64,
-    ) -> Vec<Candle> {
+        price: u128,
+        volume: u128,
+        event_time: i64,
+    ) -> Result<CandleUndo, Error> {
+        let mut candles = self.candles.write().unwrap();
+        let series = candles
+            .entry(symbol.to_string())
+            .or_default()
+            .entry(interval)
+            .or_default();
+        let period_start = event_time - event_time.rem_euclid(interval as i64);
+        if series.keys().next().is_some_and(|oldest| period_start < *oldest) {
+            // Поздняя сделка старше всех свечей в памяти относится к архиву,
+            // а без архива — если политика хранения ее сразу не вытеснит
+            if let Some(archive) = &self.archive {
+                return self.add_archived(archive.as_ref(), series, symbol, interval, price, volume, event_time);
+            }
+            if !self.retention.keeps(series, period_start, interval as i64) {
+                warn!(
+                    "Skipping {} trade at {}: older than the {}s candles kept in memory",
+                    symbol, event_time, interval
+                );
+                return Ok(CandleUndo::new(symbol, interval));
+            }
+        }
+        let undo = apply_price(series, symbol, interval, price, volume, event_time)?;
+
+        let evicted = self.retention.evict(series);
+        if let Some(archive) = &self.archive {
+            if !evicted.is_empty() {
+                archive.put_candles(symbol, interval, evicted)?;
+            }
+        }
+        Ok(undo)
+    }
+
+    fn revert(&self, mut undo: CandleUndo) -> Result<(), Error> {
+        let archived = undo.archived.take();
+        {
+            let mut candles = self.candles.write().unwrap();
+            let (symbol, interval) = undo.series();
+            let series = candles
+                .get_mut(symbol)
+                .and_then(|interval_map| interval_map.get_mut(&interval));
+            match (series, &self.archive) {
+                // Затронутые свечи с тех пор вытеснены в архив
+                (Some(series), Some(archive))
+                    if series.keys().next().is_some_and(|oldest| undo.affected_from() < *oldest) =>
+                {
+                    archive.revert(undo)?
+                }
+                (Some(series), _) => apply_revert(series, undo)?,
+                (None, _) => {}
+            }
+        }
+        if let (Some(archived), Some(archive)) = (archived, &self.archive) {
+            archive.revert(*archived)?;
+        }
+        Ok(())
+    }
+
+    fn get_candles_in_time_range_secs(&self, symbol: &str, interval: u64, from: u64, to: u64) -> Vec<Candle> {
+        if from > to {
+            return vec![];
+        }
+        // Часть диапазона, вытесненная из памяти, лежит в архиве
+        let mut result = match &self.archive {
+            Some(archive) => match self.oldest_in_memory(symbol, interval) {
+                Some(oldest) if from as i64 >= oldest => vec![],
+                Some(oldest) => archive.get_candles_in_time_range_secs(
+                    symbol,
+                    interval,
+                    from,
+                    to.min(oldest as u64 - 1),
+                ),
+                None => archive.get_candles_in_time_range_secs(symbol, interval, from, to),
+            },
+            None => vec![],
+        };
+
         let candles = self.candles.read().unwrap();
-        if let Some(interval_candles) = candles
+        let Some(series) = candles
             .get(symbol)
             .and_then(|interval_map| interval_map.get(&interval))
-        {
-            let filtered: Vec<Candle> = interval_candles
-                .iter()
-                .filter(|c| {
-                    let timestamp = c.timestamp.timestamp() as u64;
-                    let result = timestamp >= from && timestamp <= to;
-                    info!(
-                        "Filtering candle: {:?}, timestamp: {}, result: {}",
-                        c, timestamp, result
-                    );
-                    result
-                })
-                .cloned()
-                .collect();
-
-            info!("Filtered candles: {:?}", filtered);
-            filtered
-        } else {
-            info!(
-                "No candles found for symbol: {}, interval: {}, from: {}, to: {}",
-                symbol, interval, from, to
-            );
-            vec![]
-        }
-    }
-
-    pub fn get_min_max_timestamps(&self) -> Option<(i64, i64)> {
+        else {
+            if result.is_empty() {
+                info!(
+                    "No candles found for symbol: {}, interval: {}, from: {}, to: {}",
+                    symbol, interval, from, to
+                );
+            }
+            return result;
+        };
+        result.extend(
+            series
+                .range(from as i64..=to as i64)
+                .map(|(_, candle)| candle.clone()),
+        );
+        result
+    }
+
+    fn get_min_max_timestamps(&self) -> Option<(i64, i64)> {
+        let in_memory = {
+            let candles = self.candles.read().unwrap();
+            let series = candles.values().flat_map(|interval_map| interval_map.values());
+            let min = series.clone().filter_map(|s| s.keys().next().copied()).min();
+            let max = series.filter_map(|s| s.keys().next_back().copied()).max();
+            min.zip(max)
+        };
+        let archived = self.archive.as_ref().and_then(|a| a.get_min_max_timestamps());
+        match (in_memory, archived) {
+            (Some((min, max)), Some((a_min, a_max))) => Some((min.min(a_min), max.max(a_max))),
+            (bounds, archived) => bounds.or(archived),
+        }
+    }
+
+    fn snapshot(&self) -> Result<CandleSnapshot, Error> {
         let candles = self.candles.read().unwrap();
-        if candles.is_empty() {
-            return None;
+        Ok(candles
+            .iter()
+            .map(|(symbol, interval_map)| {
+                let intervals = interval_map
+                    .iter()
+                    .map(|(interval, series)| (*interval, series.values().cloned().collect()))
+                    .collect();
+                (symbol.clone(), intervals)
+            })
+            .collect())
+    }
+
+    /// Архив пишется вместе с чекпоинтом, так что свечи, вытесненные
+    /// до чекпоинта, после рестарта не потеряются и не задвоятся.
+    fn commit(&self, checkpoint: &[u8]) -> Result<bool, Error> {
+        match &self.archive {
+            Some(archive) => archive.commit(checkpoint),
+            None => Ok(false),
+        }
+    }
+
+    fn committed_checkpoint(&self) -> Result<Option<Vec<u8>>, Error> {
+        match &self.archive {
+            Some(archive) => archive.committed_checkpoint(),
+            None => Ok(None),
         }
+    }
 
-        // Собираем все timestamp из всех свечей
-        let timestamps: Vec<i64> = candles
-            .values() // Доступ ко всем HashMap<u64, Vec<Candle>>
-            .flat_map(|interval_map| interval_map.values()) // Доступ ко всем Vec<Candle>
-            .flat_map(|candle_list| candle_list.iter().map(|candle| candle.timestamp.timestamp())) // Берем timestamp из свечей
+    fn restore(&self, snapshot: CandleSnapshot) -> Result<(), Error> {
+        let restored = snapshot
+            .into_iter()
+            .map(|(symbol, interval_map)| {
+                let intervals = interval_map
+                    .into_iter()
+                    .map(|(interval, list)| {
+                        let series = list
+                            .into_iter()
+                            .map(|candle| (candle.timestamp.timestamp(), candle))
+                            .collect();
+                        (interval, series)
+                    })
+                    .collect();
+                (symbol, intervals)
+            })
             .collect();
+        *self.candles.write().unwrap() = restored;
+        Ok(())
+    }
 
-        // Ищем минимальный и максимальный timestamp
-        let min = timestamps.iter().min().cloned()?;
-        let max = timestamps.iter().max().cloned()?;
+    fn clear(&self) -> Result<(), Error> {
+        self.candles.write().unwrap().clear();
+        if let Some(archive) = &self.archive {
+            archive.clear()?;
+        }
+        Ok(())
+    }
 
-        Some((min, max))
+    fn put_candles(&self, symbol: &str, interval: u64, candles: Vec<Candle>) -> Result<(), Error> {
+        let mut store = self.candles.write().unwrap();
+        let series = store
+            .entry(symbol.to_string())
+            .or_default()
+            .entry(interval)
+            .or_default();
+        for candle in candles {
+            series.put(candle)?;
+        }
+        Ok(())
     }
+}
+
+/// Логирует ошибку чтения персистентного бэкенда и отдает пустой результат.
+pub(crate) fn read_or_default<T: Default>(result: Result<T, Error>, what: &str) -> T {
+    result.unwrap_or_else(|e| {
+        warn!("Failed to read {}: {}", what, e);
+        T::default()
+    })
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(series: &mut Series, price: u128, volume: u128, time: i64) -> CandleUndo {
+        apply_price(series, "BASE/QUOTE", 60, price, volume, time).unwrap()
+    }
+
+    fn candle(series: &Series, period_start: i64) -> &Candle {
+        &series[&period_start]
+    }
+
+    /// Применяет сделку, проверяет результат и что откат возвращает серию как была.
+    fn check_revert(series: &mut Series, price: u128, volume: u128, time: i64, check: impl Fn(&Series)) {
+        let before = series.clone();
+        let undo = trade(series, price, volume, time);
+        check(series);
+        apply_revert(series, undo).unwrap();
+        assert_eq!(*series, before);
+    }
+
+    #[test]
+    fn late_trade_takes_open_and_close_by_trade_time() {
+        let mut series = Series::new();
+        trade(&mut series, 100, 1, 10);
+        trade(&mut series, 200, 2, 50);
+
+        check_revert(&mut series, 50, 3, 5, |series| {
+            let c = candle(series, 0);
+            assert_eq!((c.open, c.high, c.low, c.close, c.volume), (50, 200, 50, 200, 6));
+            assert_eq!((c.first_trade, c.last_trade), (Some(5), Some(50)));
+        });
+        check_revert(&mut series, 300, 4, 30, |series| {
+            let c = candle(series, 0);
+            assert_eq!((c.open, c.high, c.low, c.close, c.volume), (100, 300, 100, 200, 7));
+            assert_eq!((c.first_trade, c.last_trade), (Some(10), Some(50)));
+        });
+    }
+
+    #[test]
+    fn late_trade_into_gap_rebases_following_flat_candles() {
+        let mut series = Series::new();
+        trade(&mut series, 100, 1, 0);
+        trade(&mut series, 200, 1, 250);
+        for period_start in [60, 120, 180] {
+            assert_eq!(*candle(&series, period_start), Candle::flat(period_start, 100, 0));
+        }
+
+        check_revert(&mut series, 150, 2, 70, |series| {
+            assert_eq!(*candle(series, 60), Candle::traded(60, 150, 2, 70));
+            assert_eq!(*candle(series, 120), Candle::flat(120, 150, 0));
+            assert_eq!(*candle(series, 180), Candle::flat(180, 150, 0));
+            assert_eq!(*candle(series, 240), Candle::traded(240, 200, 1, 250));
+        });
+    }
+
+    #[test]
+    fn trade_before_first_candle_fills_up_to_it() {
+        let mut series = Series::new();
+        trade(&mut series, 100, 1, 300);
+
+        check_revert(&mut series, 80, 1, 100, |series| {
+            assert_eq!(series.len(), 5);
+            assert_eq!(*candle(series, 60), Candle::traded(60, 80, 1, 100));
+            for period_start in [120, 180, 240] {
+                assert_eq!(*candle(series, period_start), Candle::flat(period_start, 80, 0));
+            }
+            assert_eq!(*candle(series, 300), Candle::traded(300, 100, 1, 300));
+        });
+    }
+
+    /// Стор с тремя минутными свечами в памяти: 180 и 240 дозаполнены ценой 100,
+    /// 300 со сделкой по 200. Свечи 0..120 вытеснены в архив, если он есть.
+    fn evicting_store(archive: bool) -> InMemoryCandleStore {
+        let mut store = InMemoryCandleStore::new(Retention::Count(3));
+        if archive {
+            store = store.with_archive(Box::new(InMemoryCandleStore::new(Retention::Forever)));
+        }
+        store.add_price("BASE/QUOTE", 60, 100, 1, 0).unwrap();
+        store.add_price("BASE/QUOTE", 60, 200, 1, 300).unwrap();
+        store
+    }
+
+    fn all_candles(store: &InMemoryCandleStore) -> Vec<Candle> {
+        store.get_candles_in_time_range_secs("BASE/QUOTE", 60, 0, 1000)
+    }
+
+    #[test]
+    fn archived_late_trade_rebases_flat_candles_in_memory() {
+        let store = evicting_store(true);
+        let before = all_candles(&store);
+        assert_eq!(before.len(), 6);
+
+        let undo = store.add_price("BASE/QUOTE", 60, 150, 2, 70).unwrap();
+        assert_eq!(undo.affected_from(), 60);
+        let after = all_candles(&store);
+        assert_eq!(after[1], Candle::traded(60, 150, 2, 70));
+        assert_eq!(after[2], Candle::flat(120, 150, 0));
+        // Граница архива и памяти: цена не прыгает обратно на 100
+        assert_eq!(after[3], Candle::flat(180, 150, 0));
+        assert_eq!(after[4], Candle::flat(240, 150, 0));
+        assert_eq!(after[5], Candle::traded(300, 200, 1, 300));
+
+        store.revert(undo).unwrap();
+        assert_eq!(all_candles(&store), before);
+    }
+
+    #[test]
+    fn trade_older_than_retention_is_skipped_without_archive() {
+        let store = evicting_store(false);
+        let before = all_candles(&store);
+        assert_eq!(before.len(), 3);
+
+        let undo = store.add_price("BASE/QUOTE", 60, 150, 2, 70).unwrap();
+        assert_eq!(undo.affected_from(), i64::MAX);
+        assert_eq!(all_candles(&store), before);
+        store.revert(undo).unwrap();
+        assert_eq!(all_candles(&store), before);
+
+        // Сделка в пределах памяти применяется как обычно
+        store.add_price("BASE/QUOTE", 60, 150, 2, 190).unwrap();
+        assert_eq!(all_candles(&store)[0], Candle::traded(180, 150, 2, 190));
+    }
+
+    #[test]
+    fn retention_keeps_only_candles_it_would_not_evict() {
+        let mut series = Series::new();
+        trade(&mut series, 100, 1, 180);
+        trade(&mut series, 200, 1, 300);
+
+        assert!(Retention::Count(5).keeps(&series, 60, 60));
+        assert!(!Retention::Count(4).keeps(&series, 60, 60));
+        assert!(Retention::Age(240).keeps(&series, 60, 60));
+        assert!(!Retention::Age(180).keeps(&series, 60, 60));
+        assert!(Retention::Forever.keeps(&series, 0, 60));
+    }
 }
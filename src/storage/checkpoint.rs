@@ -0,0 +1,191 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::indexer::dedup::EventKey;
+use crate::indexer::spot_order::SpotOrder;
+use crate::storage::candles::CandleSnapshot;
+use crate::storage::timeframes::TradeSnapshot;
+
+/// Версия формата чекпоинта. Без поля — чекпоинт до версионирования.
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+/// Состояние индексатора после полностью обработанного блока `last_block`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(default)]
+    pub version: u32,
+    pub last_block: i64,
+    pub candles: CandleSnapshot,
+    /// Сырые сделки для секундных разрешений.
+    #[serde(default)]
+    pub trades: TradeSnapshot,
+    pub order_books: HashMap<String, Vec<SpotOrder>>,
+    /// Уже примененные события, чтобы не применить их повторно после рестарта.
+    #[serde(default)]
+    pub applied_events: Vec<EventKey>,
+}
+
+/// Локальный файл чекпоинта, пишется не чаще чем раз в `interval`.
+pub struct CheckpointStore {
+    path: PathBuf,
+    interval: Duration,
+    last_saved: Mutex<Instant>,
+}
+
+impl CheckpointStore {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+            last_saved: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn load(&self) -> Result<Option<Checkpoint>, Error> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read(&self.path)?;
+        Self::parse(&content, &self.path.display().to_string())
+    }
+
+    /// Разбирает чекпоинт, прочитанный из `origin`. Чекпоинт старого формата
+    /// со свечами в f64 дает `None`: история строится заново. Любой другой
+    /// нечитаемый чекпоинт — ошибка, чтобы не потерять состояние молча.
+    pub fn parse(content: &[u8], origin: &str) -> Result<Option<Checkpoint>, Error> {
+        let checkpoint: Checkpoint = match serde_json::from_slice(content) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) if is_legacy(content) => {
+                warn!(
+                    "Checkpoint {} has legacy f64 candles, rebuilding from scratch: {}",
+                    origin, e
+                );
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(Error::ConfigError(format!(
+                    "Unreadable checkpoint {}: {}",
+                    origin, e
+                )))
+            }
+        };
+        if checkpoint.version > CHECKPOINT_VERSION {
+            return Err(Error::ConfigError(format!(
+                "Checkpoint {} has version {}, newer than supported {}",
+                origin, checkpoint.version, CHECKPOINT_VERSION
+            )));
+        }
+        info!(
+            "Loaded checkpoint from {} at block {}",
+            origin, checkpoint.last_block
+        );
+        Ok(Some(checkpoint))
+    }
+
+    /// Пора ли писать следующий чекпоинт.
+    pub fn is_due(&self) -> bool {
+        self.last_saved.lock().unwrap().elapsed() >= self.interval
+    }
+
+    /// Отмечает чекпоинт, записанный в обход файла (вместе со свечами в sled).
+    pub fn mark_saved(&self) {
+        *self.last_saved.lock().unwrap() = Instant::now();
+    }
+
+    /// Пишет чекпоинт во временный файл и атомарно переименовывает его,
+    /// чтобы падение посреди записи не испортило предыдущий чекпоинт.
+    pub fn save(&self, checkpoint: &Checkpoint) -> Result<(), Error> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(checkpoint)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        *self.last_saved.lock().unwrap() = Instant::now();
+
+        info!(
+            "Checkpoint saved to {} at block {}",
+            self.path.display(),
+            checkpoint.last_block
+        );
+        Ok(())
+    }
+}
+
+/// Чекпоинт без версии, где цены свечей — дробные числа.
+fn is_legacy(content: &[u8]) -> bool {
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(content) else {
+        return false;
+    };
+    value.get("version").is_none()
+        && value["candles"]
+            .as_object()
+            .into_iter()
+            .flat_map(|symbols| symbols.values())
+            .filter_map(|intervals| intervals.as_object())
+            .flat_map(|intervals| intervals.values())
+            .filter_map(|candles| candles.as_array())
+            .flatten()
+            .any(|candle| candle["open"].is_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::candles::Candle;
+
+    #[test]
+    fn unversioned_f64_checkpoint_is_legacy() {
+        let content = br#"{
+            "last_block": 10,
+            "candles": {"A/B": {"60": [{
+                "open": 1.5, "high": 2.0, "low": 1.0, "close": 1.25, "volume": 3.0,
+                "timestamp": "2024-01-01T00:00:00Z"
+            }]}},
+            "order_books": {}
+        }"#;
+        assert!(CheckpointStore::parse(content, "legacy").unwrap().is_none());
+    }
+
+    #[test]
+    fn current_checkpoint_round_trips() {
+        let path = std::env::temp_dir().join(format!("checkpoint-test-{}.json", uuid::Uuid::new_v4()));
+        let store = CheckpointStore::new(&path, Duration::from_secs(3600));
+        // Цены за пределами u64 и f64 не теряют точности
+        let mut candle = Candle::traded(60, u128::MAX - 1, u64::MAX as u128 + 1, 61);
+        candle.high = u128::MAX;
+        let checkpoint = Checkpoint {
+            version: CHECKPOINT_VERSION,
+            last_block: 42,
+            candles: HashMap::from([("A/B".to_string(), HashMap::from([(60, vec![candle])]))]),
+            trades: Default::default(),
+            order_books: HashMap::new(),
+            applied_events: vec![EventKey {
+                transaction_hash: "0xabc".to_string(),
+                log_index: 3,
+            }],
+        };
+        store.save(&checkpoint).unwrap();
+        let loaded = store.load().unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.version, CHECKPOINT_VERSION);
+        assert_eq!(loaded.last_block, checkpoint.last_block);
+        assert_eq!(loaded.candles, checkpoint.candles);
+        assert_eq!(loaded.applied_events, checkpoint.applied_events);
+    }
+
+    #[test]
+    fn corrupt_or_newer_checkpoint_is_an_error() {
+        assert!(CheckpointStore::parse(b"{\"last_block\": 10, \"cand", "corrupt").is_err());
+        assert!(CheckpointStore::parse(b"[]", "corrupt").is_err());
+        let newer = format!(
+            "{{\"version\": {}, \"last_block\": 1, \"candles\": {{}}, \"order_books\": {{}}}}",
+            CHECKPOINT_VERSION + 1
+        );
+        assert!(CheckpointStore::parse(newer.as_bytes(), "newer").is_err());
+    }
+}
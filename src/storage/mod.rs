@@ -1,2 +1,6 @@
 pub mod order_book;
 pub mod candles;
+pub mod checkpoint;
+pub mod retention;
+pub mod sled_candles;
+pub mod timeframes;
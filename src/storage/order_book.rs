@@ -1,6 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, RwLock};
 
+use crate::indexer::source::Indexer;
 use crate::indexer::spot_order::{OrderType, SpotOrder};
 
 pub struct OrderBook {
@@ -72,6 +73,12 @@ impl OrderBook {
         None
     }
 
+    /// Ищет ордер по id в обеих сторонах книги.
+    pub fn find_order(&self, id: &str) -> Option<SpotOrder> {
+        self.get_order(id, OrderType::Buy)
+            .or_else(|| self.get_order(id, OrderType::Sell))
+    }
+
     pub fn update_order(&self, order: SpotOrder) {
         self.remove_order(&order.id, Some(order.order_type));
         self.add_order(order);
@@ -99,6 +106,28 @@ impl OrderBook {
         }
     }
 
+    /// Заменяет все ордера стороны новым набором. Ордера, которых нет в наборе,
+    /// из книги пропадают.
+    pub fn replace_orders(&self, order_type: OrderType, orders: Vec<SpotOrder>) {
+        let mut tree = BTreeMap::new();
+        for order in orders.into_iter().filter(|o| o.order_type == order_type) {
+            tree.entry(order.price).or_insert(Vec::new()).push(order);
+        }
+
+        let mut target_tree = match order_type {
+            OrderType::Buy => self.buy_orders.write().unwrap(),
+            OrderType::Sell => self.sell_orders.write().unwrap(),
+        };
+        *target_tree = tree;
+    }
+
+    /// Все ордера книги, сначала покупка, затем продажа.
+    pub fn snapshot(&self) -> Vec<SpotOrder> {
+        let mut orders = self.get_orders_in_range(0, u128::MAX, OrderType::Buy);
+        orders.extend(self.get_orders_in_range(0, u128::MAX, OrderType::Sell));
+        orders
+    }
+
     fn remove_order_from_tree(&self, target_tree: &mut BTreeMap<u128, Vec<SpotOrder>>, id: &str) {
         let mut empty_keys = Vec::new();
 
@@ -114,3 +143,70 @@ impl OrderBook {
         }
     }
 }
+
+/// Книги ордеров по рынкам, ключ — символ рынка.
+#[derive(Default)]
+pub struct OrderBooks {
+    books: RwLock<HashMap<String, Arc<OrderBook>>>,
+}
+
+impl OrderBooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<Arc<OrderBook>> {
+        self.books.read().unwrap().get(symbol).cloned()
+    }
+
+    pub fn get_or_create(&self, symbol: &str) -> Arc<OrderBook> {
+        if let Some(book) = self.get(symbol) {
+            return book;
+        }
+        let mut books = self.books.write().unwrap();
+        Arc::clone(books.entry(symbol.to_string()).or_default())
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, Vec<SpotOrder>> {
+        self.books
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(symbol, book)| (symbol.clone(), book.snapshot()))
+            .collect()
+    }
+
+    /// Пересоздает книги из снимка чекпоинта.
+    pub fn restore(&self, snapshot: HashMap<String, Vec<SpotOrder>>) {
+        let mut books = self.books.write().unwrap();
+        books.clear();
+        for (symbol, orders) in snapshot {
+            let book = OrderBook::new();
+            for order in orders {
+                book.add_order(order);
+            }
+            books.insert(symbol, Arc::new(book));
+        }
+    }
+}
+
+/// Книги ордеров каждого включенного индексатора, чтобы сравнивать их между собой.
+pub struct IndexerOrderBooks {
+    primary: Indexer,
+    books: HashMap<Indexer, Arc<OrderBooks>>,
+}
+
+impl IndexerOrderBooks {
+    pub fn new(primary: Indexer, books: HashMap<Indexer, Arc<OrderBooks>>) -> Self {
+        Self { primary, books }
+    }
+
+    /// Книги индексатора, или основного, если `indexer` не задан.
+    pub fn get(&self, indexer: Option<Indexer>) -> Option<Arc<OrderBooks>> {
+        self.books.get(&indexer.unwrap_or(self.primary)).cloned()
+    }
+
+    pub fn indexers(&self) -> Vec<Indexer> {
+        self.books.keys().copied().collect()
+    }
+}
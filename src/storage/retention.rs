@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use crate::config::env::ev;
+use crate::error::Error;
+use crate::storage::candles::Candle;
+use crate::storage::timeframes::BASE_INTERVAL;
+
+/// Сколько свечей серии держать по умолчанию, если `CANDLE_RETENTION` не задан:
+/// неделя минутных свечей. Дневная серия с тем же лимитом хранит D/W историю
+/// за десятилетия.
+const DEFAULT_MAX_CANDLES: usize = 7 * 24 * 60;
+
+/// Сколько свечей серии держать в памяти.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    /// Не больше N последних свечей.
+    Count(usize),
+    /// Свечи не старше N секунд от последней свечи серии. Отсчет от свечи,
+    /// а не от текущего времени, чтобы догонка истории не выбрасывала все подряд.
+    Age(i64),
+    Forever,
+}
+
+impl Retention {
+    /// Убирает из серии вышедшие за лимит свечи и возвращает их.
+    pub fn evict(&self, series: &mut BTreeMap<i64, Candle>) -> Vec<Candle> {
+        let keep_from = match *self {
+            Retention::Count(count) if series.len() > count => {
+                series.keys().rev().nth(count.saturating_sub(1)).copied()
+            }
+            Retention::Age(age) => series.keys().next_back().map(|last| last - age),
+            _ => None,
+        };
+        let Some(keep_from) = keep_from else {
+            return vec![];
+        };
+        let kept = series.split_off(&keep_from);
+        std::mem::replace(series, kept).into_values().collect()
+    }
+
+    /// Останется ли после вытеснения свеча с началом `period_start`, если
+    /// добавить ее в серию с шагом `step`. Серия в памяти без пропусков:
+    /// свеча раньше самой старой дотянет за собой дозаполненные до нее.
+    pub fn keeps(&self, series: &BTreeMap<i64, Candle>, period_start: i64, step: i64) -> bool {
+        let Some(&last) = series.keys().next_back() else {
+            return true;
+        };
+        match *self {
+            Retention::Count(count) => (last - period_start) / step < count as i64,
+            Retention::Age(age) => period_start >= last - age,
+            Retention::Forever => true,
+        }
+    }
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention::Count(DEFAULT_MAX_CANDLES)
+    }
+}
+
+impl Retention {
+    /// Читает `CANDLE_RETENTION` — лимит каждой серии стора, минутной
+    /// и дневной. Лимит — число свечей (`10080`), возраст с суффиксом
+    /// `s`/`m`/`h`/`d`/`w` (`30d`) или `forever`. Запись вида `60=30d` тоже
+    /// принимается, лимит любого другого интервала — ошибка: отдельно
+    /// он не задается.
+    pub fn from_env() -> Result<Self, Error> {
+        match ev("CANDLE_RETENTION") {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let mut retention = Self::default();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (interval, limit) = match entry.split_once('=') {
+                Some((interval, limit)) => (interval.trim(), limit.trim()),
+                None => ("default", entry),
+            };
+            if interval != "default" && interval.parse::<u64>().ok() != Some(BASE_INTERVAL) {
+                return Err(Error::ConfigError(format!(
+                    "CANDLE_RETENTION entry {}: retention is set for the base {}s series only",
+                    entry, BASE_INTERVAL
+                )));
+            }
+            retention = parse_retention(limit).ok_or_else(|| invalid(entry))?;
+        }
+        Ok(retention)
+    }
+}
+
+fn parse_retention(limit: &str) -> Option<Retention> {
+    if limit == "forever" {
+        return Some(Retention::Forever);
+    }
+    if let Ok(count) = limit.parse() {
+        return Some(Retention::Count(count));
+    }
+    let (number, unit) = limit.split_at(limit.len().checked_sub(1)?);
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return None,
+    };
+    Some(Retention::Age(number.parse::<i64>().ok()? * seconds))
+}
+
+fn invalid(entry: &str) -> Error {
+    Error::ConfigError(format!("Invalid CANDLE_RETENTION entry: {}", entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(starts: impl IntoIterator<Item = i64>) -> BTreeMap<i64, Candle> {
+        starts
+            .into_iter()
+            .map(|start| (start, Candle::flat(start, 100, 0)))
+            .collect()
+    }
+
+    #[test]
+    fn parses_count_age_and_forever() {
+        assert_eq!(Retention::parse("10080").unwrap(), Retention::Count(10080));
+        assert_eq!(Retention::parse("90s").unwrap(), Retention::Age(90));
+        assert_eq!(Retention::parse("2h").unwrap(), Retention::Age(2 * 3600));
+        assert_eq!(Retention::parse("30d").unwrap(), Retention::Age(30 * 86400));
+        assert_eq!(Retention::parse("1w").unwrap(), Retention::Age(604800));
+        assert_eq!(Retention::parse("forever").unwrap(), Retention::Forever);
+        assert_eq!(Retention::parse("60=30d").unwrap(), Retention::Age(30 * 86400));
+        assert_eq!(Retention::parse("default=5").unwrap(), Retention::Count(5));
+        assert_eq!(Retention::parse("").unwrap(), Retention::default());
+    }
+
+    #[test]
+    fn rejects_other_intervals_and_garbage() {
+        assert!(Retention::parse("300=10").is_err());
+        assert!(Retention::parse("86400=forever").is_err());
+        assert!(Retention::parse("abc").is_err());
+        assert!(Retention::parse("10y").is_err());
+        assert!(Retention::parse("d").is_err());
+    }
+
+    #[test]
+    fn count_keeps_exactly_the_last_candles() {
+        let mut candles = series((0..5).map(|i| i * 60));
+        assert!(Retention::Count(5).evict(&mut candles).is_empty());
+
+        let evicted = Retention::Count(3).evict(&mut candles);
+        assert_eq!(evicted.iter().map(|c| c.timestamp.timestamp()).collect::<Vec<_>>(), [0, 60]);
+        assert_eq!(candles.keys().copied().collect::<Vec<_>>(), [120, 180, 240]);
+    }
+
+    #[test]
+    fn age_keeps_candles_at_the_boundary() {
+        let mut candles = series((0..5).map(|i| i * 60));
+        let evicted = Retention::Age(120).evict(&mut candles);
+        assert_eq!(evicted.iter().map(|c| c.timestamp.timestamp()).collect::<Vec<_>>(), [0, 60]);
+        // 240 - 120 = 120: свеча ровно на границе остается
+        assert_eq!(candles.keys().copied().collect::<Vec<_>>(), [120, 180, 240]);
+
+        assert!(Retention::Forever.evict(&mut candles).is_empty());
+        assert_eq!(candles.len(), 3);
+    }
+}
@@ -0,0 +1,308 @@
+use log::info;
+use sled::transaction::{ConflictableTransactionResult, TransactionError, Transactional};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::storage::candles::{
+    apply_price, apply_revert, read_or_default, Candle, CandleSeries, CandleSnapshot, CandleStore,
+    CandleUndo,
+};
+
+/// Изменения серии после последнего сброса на диск: `None` — свеча удалена.
+type Pending = BTreeMap<i64, Option<Candle>>;
+
+const CHECKPOINT_KEY: &[u8] = b"checkpoint";
+
+/// Стор свечей во встроенной базе sled. Ключ свечи — символ, интервал и время
+/// начала в big-endian, поэтому серия лежит на диске по порядку и читается
+/// диапазоном. Изменения копятся в памяти и пишутся одной транзакцией вместе
+/// с чекпоинтом, так что состояние на диске всегда соответствует чекпоинту.
+pub struct SledCandleStore {
+    db: sled::Db,
+    candles: sled::Tree,
+    /// Ключи серий, чтобы не сканировать всю базу ради границ по времени.
+    series: sled::Tree,
+    /// Чекпоинт индексатора, записанный вместе со свечами.
+    meta: sled::Tree,
+    pending: Mutex<HashMap<(String, u64), Pending>>,
+}
+
+impl SledCandleStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let db = sled::open(path)?;
+        let candles = db.open_tree("candles")?;
+        let series = db.open_tree("series")?;
+        let meta = db.open_tree("meta")?;
+        info!("Candle database opened, {} candles stored", candles.len());
+        Ok(Self {
+            db,
+            candles,
+            series,
+            meta,
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn series_view<'a>(
+        &'a self,
+        symbol: &str,
+        interval: u64,
+        pending: &'a mut HashMap<(String, u64), Pending>,
+    ) -> SledSeries<'a> {
+        SledSeries {
+            tree: &self.candles,
+            prefix: series_prefix(symbol, interval),
+            pending: pending.entry((symbol.to_string(), interval)).or_default(),
+        }
+    }
+
+    /// Пишет накопленные изменения и `checkpoint`, если он задан, одной транзакцией.
+    fn flush(&self, checkpoint: Option<&[u8]>) -> Result<(), Error> {
+        // Лок держим до конца записи, чтобы пачка не разошлась с новыми изменениями
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() && checkpoint.is_none() {
+            return Ok(());
+        }
+
+        let mut batch = sled::Batch::default();
+        let mut series_batch = sled::Batch::default();
+        let mut written = 0;
+        for ((symbol, interval), changes) in pending.iter() {
+            let prefix = series_prefix(symbol, *interval);
+            series_batch.insert(prefix.clone(), b"".to_vec());
+            for (period_start, candle) in changes {
+                let key = candle_key(&prefix, *period_start);
+                match candle {
+                    Some(candle) => batch.insert(key, serde_json::to_vec(candle)?),
+                    None => batch.remove(key),
+                }
+                written += 1;
+            }
+        }
+        (&self.candles, &self.series, &self.meta)
+            .transaction(|(candles, series, meta)| -> ConflictableTransactionResult<(), ()> {
+                candles.apply_batch(&batch)?;
+                series.apply_batch(&series_batch)?;
+                if let Some(checkpoint) = checkpoint {
+                    meta.insert(CHECKPOINT_KEY, checkpoint)?;
+                }
+                Ok(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => Error::from(e),
+                TransactionError::Abort(()) => Error::SledError(sled::Error::Unsupported(
+                    "candle transaction aborted".to_string(),
+                )),
+            })?;
+        self.db.flush()?;
+        pending.clear();
+        info!("Flushed {} candle changes to disk", written);
+        Ok(())
+    }
+
+    fn read_range(&self, symbol: &str, interval: u64, from: i64, to: i64) -> Result<Vec<Candle>, Error> {
+        let prefix = series_prefix(symbol, interval);
+        let mut candles = BTreeMap::new();
+        for item in self
+            .candles
+            .range(candle_key(&prefix, from)..=candle_key(&prefix, to))
+        {
+            let (key, value) = item?;
+            candles.insert(key_period(&key), serde_json::from_slice::<Candle>(&value)?);
+        }
+
+        let pending = self.pending.lock().unwrap();
+        if let Some(changes) = pending.get(&(symbol.to_string(), interval)) {
+            for (period_start, candle) in changes.range(from..=to) {
+                match candle {
+                    Some(candle) => candles.insert(*period_start, candle.clone()),
+                    None => candles.remove(period_start),
+                };
+            }
+        }
+        Ok(candles.into_values().collect())
+    }
+
+    fn read_bounds(&self) -> Result<Option<(i64, i64)>, Error> {
+        let mut bounds: Option<(i64, i64)> = None;
+        let mut extend = |min: i64, max: i64| {
+            bounds = Some(match bounds {
+                Some((lo, hi)) => (lo.min(min), hi.max(max)),
+                None => (min, max),
+            });
+        };
+
+        for item in self.series.iter() {
+            let (prefix, _) = item?;
+            let first = self.candles.scan_prefix(&prefix).next().transpose()?;
+            let last = self.candles.scan_prefix(&prefix).next_back().transpose()?;
+            if let (Some((first, _)), Some((last, _))) = (first, last) {
+                extend(key_period(&first), key_period(&last));
+            }
+        }
+        for changes in self.pending.lock().unwrap().values() {
+            let mut present = changes.iter().filter(|(_, c)| c.is_some()).map(|(t, _)| *t);
+            if let Some(first) = present.next() {
+                extend(first, present.last().unwrap_or(first));
+            }
+        }
+        Ok(bounds)
+    }
+}
+
+impl CandleStore for SledCandleStore {
+    fn add_price(
+        &self,
+        symbol: &str,
+        interval: u64,
+        price: u128,
+        volume: u128,
+        event_time: i64,
+    ) -> Result<CandleUndo, Error> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut series = self.series_view(symbol, interval, &mut pending);
+        apply_price(&mut series, symbol, interval, price, volume, event_time)
+    }
+
+    fn revert(&self, undo: CandleUndo) -> Result<(), Error> {
+        let (symbol, interval) = undo.series();
+        let (symbol, interval) = (symbol.to_string(), interval);
+        let mut pending = self.pending.lock().unwrap();
+        let mut series = self.series_view(&symbol, interval, &mut pending);
+        apply_revert(&mut series, undo)
+    }
+
+    fn get_candles_in_time_range_secs(&self, symbol: &str, interval: u64, from: u64, to: u64) -> Vec<Candle> {
+        if from > to {
+            return vec![];
+        }
+        read_or_default(
+            self.read_range(symbol, interval, from as i64, to as i64),
+            "candles",
+        )
+    }
+
+    fn get_min_max_timestamps(&self) -> Option<(i64, i64)> {
+        read_or_default(self.read_bounds(), "candle bounds")
+    }
+
+    fn snapshot(&self) -> Result<CandleSnapshot, Error> {
+        Ok(CandleSnapshot::new())
+    }
+
+    fn commit(&self, checkpoint: &[u8]) -> Result<bool, Error> {
+        self.flush(Some(checkpoint))?;
+        Ok(true)
+    }
+
+    fn committed_checkpoint(&self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.meta.get(CHECKPOINT_KEY)?.map(|value| value.to_vec()))
+    }
+
+    /// Чекпоинт персистентного стора свечей не содержит. Непустой снимок
+    /// остается от хранения в памяти, его переносим на диск.
+    fn restore(&self, snapshot: CandleSnapshot) -> Result<(), Error> {
+        if snapshot.is_empty() {
+            return Ok(());
+        }
+        {
+            let mut pending = self.pending.lock().unwrap();
+            for (symbol, interval_map) in snapshot {
+                for (interval, candles) in interval_map {
+                    let changes = pending.entry((symbol.clone(), interval)).or_default();
+                    for candle in candles {
+                        changes.insert(candle.timestamp.timestamp(), Some(candle));
+                    }
+                }
+            }
+        }
+        self.flush(None)
+    }
+
+    fn clear(&self) -> Result<(), Error> {
+        self.pending.lock().unwrap().clear();
+        self.candles.clear()?;
+        self.series.clear()?;
+        self.meta.clear()?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn put_candles(&self, symbol: &str, interval: u64, candles: Vec<Candle>) -> Result<(), Error> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut series = self.series_view(symbol, interval, &mut pending);
+        for candle in candles {
+            series.put(candle)?;
+        }
+        Ok(())
+    }
+}
+
+/// Серия на диске с наложенными несброшенными изменениями.
+struct SledSeries<'a> {
+    tree: &'a sled::Tree,
+    prefix: Vec<u8>,
+    pending: &'a mut Pending,
+}
+
+impl CandleSeries for SledSeries<'_> {
+    fn last(&mut self) -> Result<Option<Candle>, Error> {
+        let pending_last = self.pending.values().rev().find_map(|c| c.clone());
+        let mut disk_last = None;
+        for item in self.tree.scan_prefix(&self.prefix).rev() {
+            let (key, value) = item?;
+            // Измененные в памяти свечи учтены в `pending_last` или удалены
+            if self.pending.contains_key(&key_period(&key)) {
+                continue;
+            }
+            disk_last = Some(serde_json::from_slice::<Candle>(&value)?);
+            break;
+        }
+        Ok(match (pending_last, disk_last) {
+            (Some(a), Some(b)) => Some(if a.timestamp >= b.timestamp { a } else { b }),
+            (a, b) => a.or(b),
+        })
+    }
+
+    fn get(&mut self, period_start: i64) -> Result<Option<Candle>, Error> {
+        if let Some(candle) = self.pending.get(&period_start) {
+            return Ok(candle.clone());
+        }
+        match self.tree.get(candle_key(&self.prefix, period_start))? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, candle: Candle) -> Result<(), Error> {
+        self.pending.insert(candle.timestamp.timestamp(), Some(candle));
+        Ok(())
+    }
+
+    fn remove(&mut self, period_start: i64) -> Result<(), Error> {
+        self.pending.insert(period_start, None);
+        Ok(())
+    }
+}
+
+/// Префикс серии: символ, нулевой байт, интервал в big-endian.
+fn series_prefix(symbol: &str, interval: u64) -> Vec<u8> {
+    let mut prefix = symbol.as_bytes().to_vec();
+    prefix.push(0);
+    prefix.extend_from_slice(&interval.to_be_bytes());
+    prefix
+}
+
+fn candle_key(prefix: &[u8], period_start: i64) -> Vec<u8> {
+    let mut key = prefix.to_vec();
+    key.extend_from_slice(&(period_start.max(0) as u64).to_be_bytes());
+    key
+}
+
+fn key_period(key: &[u8]) -> i64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&key[key.len() - 8..]);
+    u64::from_be_bytes(bytes) as i64
+}
@@ -0,0 +1,606 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::config::env::ev;
+use crate::error::Error;
+use crate::storage::candles::{Candle, CandleSnapshot, CandleStore, CandleUndo};
+
+/// Интервал базовой серии: разрешения от минуты до дня строятся из нее.
+pub const BASE_INTERVAL: u64 = 60;
+/// Интервал дневной серии. Она пишется рядом с базовой, из нее строятся
+/// разрешения от дня, так что D/W история переживает вытеснение минутных свечей.
+pub const DAY_INTERVAL: u64 = 86400;
+/// Сколько секунд сырых сделок держать для разрешений меньше минуты.
+const DEFAULT_TRADE_RETENTION_SECS: i64 = 86400;
+/// Сколько агрегированных серий (symbol, resolution) держать в кэше.
+const DEFAULT_AGGREGATE_CACHE_SIZE: usize = 64;
+
+/// Сделка для разрешений меньше базового интервала, в сырых единицах контракта.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    pub price: u128,
+    pub volume: u128,
+    pub timestamp: i64,
+}
+
+/// Сырые сделки: symbol -> сделки по возрастанию времени.
+pub type TradeSnapshot = HashMap<String, VecDeque<Trade>>;
+
+/// Состояние до `add_trade`, по нему сделку можно откатить.
+#[derive(Debug, Clone)]
+pub struct TradeUndo {
+    symbol: String,
+    timestamp: i64,
+    candle: CandleUndo,
+    day: CandleUndo,
+}
+
+/// Свечи любого разрешения. В сторе пишутся базовая минутная и дневная серии,
+/// рядом в памяти держатся сырые сделки за `TRADE_RETENTION_SECS` для
+/// секундных разрешений. Остальные разрешения собираются из ближайшей серии
+/// при запросе, часто запрашиваемые кэшируются.
+pub struct Timeframes {
+    store: Arc<dyn CandleStore>,
+    trades: RwLock<TradeSnapshot>,
+    trade_retention: i64,
+    cache: Mutex<AggregateCache>,
+}
+
+impl Timeframes {
+    pub fn from_env(store: Arc<dyn CandleStore>) -> Self {
+        Self {
+            store,
+            trades: RwLock::new(HashMap::new()),
+            trade_retention: ev("TRADE_RETENTION_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TRADE_RETENTION_SECS),
+            cache: Mutex::new(AggregateCache::new(
+                ev("AGGREGATE_CACHE_SIZE")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_AGGREGATE_CACHE_SIZE),
+            )),
+        }
+    }
+
+    /// Добавляет сделку в базовую и дневную серии и в сырые сделки.
+    pub fn add_trade(
+        &self,
+        symbol: &str,
+        price: u128,
+        volume: u128,
+        event_time: i64,
+    ) -> Result<TradeUndo, Error> {
+        let candle = self
+            .store
+            .add_price(symbol, BASE_INTERVAL, price, volume, event_time)?;
+        let day = match self
+            .store
+            .add_price(symbol, DAY_INTERVAL, price, volume, event_time)
+        {
+            Ok(day) => day,
+            Err(e) => {
+                self.store.revert(candle)?;
+                return Err(e);
+            }
+        };
+
+        {
+            let mut trades = self.trades.write().unwrap();
+            let trades = trades.entry(symbol.to_string()).or_default();
+            let index = trades.partition_point(|t| t.timestamp <= event_time);
+            trades.insert(
+                index,
+                Trade {
+                    price,
+                    volume,
+                    timestamp: event_time,
+                },
+            );
+            if let Some(newest) = trades.back().map(|t| t.timestamp) {
+                let expired =
+                    trades.partition_point(|t| t.timestamp < newest - self.trade_retention);
+                trades.drain(..expired);
+            }
+        }
+
+        self.cache
+            .lock()
+            .unwrap()
+            .invalidate(symbol, candle.affected_from(), day.affected_from());
+        Ok(TradeUndo {
+            symbol: symbol.to_string(),
+            timestamp: event_time,
+            candle,
+            day,
+        })
+    }
+
+    /// Откатывает `add_trade`. Откаты применяются в порядке, обратном добавлению.
+    pub fn revert(&self, undo: TradeUndo) -> Result<(), Error> {
+        {
+            let mut trades = self.trades.write().unwrap();
+            if let Some(trades) = trades.get_mut(&undo.symbol) {
+                // Сделка с тем же временем, добавленная последней, стоит последней среди равных
+                let index = trades.partition_point(|t| t.timestamp <= undo.timestamp);
+                if index > 0 && trades[index - 1].timestamp == undo.timestamp {
+                    trades.remove(index - 1);
+                }
+            }
+        }
+
+        let affected_from = undo.candle.affected_from();
+        let day_affected_from = undo.day.affected_from();
+        self.store.revert(undo.day)?;
+        self.store.revert(undo.candle)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .invalidate(&undo.symbol, affected_from, day_affected_from);
+        Ok(())
+    }
+
+    /// Строит дневную серию из базовой, если ее еще нет: стор, записанный
+    /// до появления дневной серии, иначе отдавал бы D/W только с момента
+    /// обновления.
+    pub fn rebuild_daily<'a>(&self, symbols: impl IntoIterator<Item = &'a str>) -> Result<(), Error> {
+        for symbol in symbols {
+            let all = (0, i64::MAX as u64);
+            if !self
+                .store
+                .get_candles_in_time_range_secs(symbol, DAY_INTERVAL, all.0, all.1)
+                .is_empty()
+            {
+                continue;
+            }
+            let base = self
+                .store
+                .get_candles_in_time_range_secs(symbol, BASE_INTERVAL, all.0, all.1);
+            if base.is_empty() {
+                continue;
+            }
+            let days = aggregate(base, DAY_INTERVAL as i64);
+            info!("Rebuilt {} daily candles of {} from minute candles", days.len(), symbol);
+            self.store.put_candles(symbol, DAY_INTERVAL, days)?;
+        }
+        self.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Свечи разрешения `resolution` (секунды) с началом в `[from, to]`,
+    /// по возрастанию времени. Разрешение от минуты должно быть кратно
+    /// минуте, меньше минуты — делить ее нацело. Кратные дню собираются
+    /// из дневной серии.
+    pub fn get_candles_in_time_range_secs(
+        &self,
+        symbol: &str,
+        resolution: u64,
+        from: u64,
+        to: u64,
+    ) -> Vec<Candle> {
+        let Some(source) = Source::for_resolution(resolution) else {
+            warn!(
+                "Unsupported candle resolution {} for {}",
+                resolution, symbol
+            );
+            return vec![];
+        };
+        let resolution = resolution as i64;
+        // Первая свеча, начинающаяся не раньше `from`, и последняя, начинающаяся до `to`
+        let lo = bucket_start(from as i64 + resolution - 1, resolution);
+        let hi = bucket_start(to as i64, resolution);
+        if lo > hi {
+            return vec![];
+        }
+
+        match source {
+            Source::Trades => self.aggregate_trades(symbol, resolution, lo, hi),
+            Source::Series(interval) if resolution == interval as i64 => self
+                .store
+                .get_candles_in_time_range_secs(symbol, interval, lo as u64, hi as u64),
+            Source::Series(interval) => {
+                // Лок держим на время сборки, чтобы запись между чтением
+                // серии и вставкой в кэш не оставила устаревшие свечи
+                let mut cache = self.cache.lock().unwrap();
+                if let Some(candles) = cache.get(symbol, resolution, lo, hi) {
+                    return candles;
+                }
+                let base = self.store.get_candles_in_time_range_secs(
+                    symbol,
+                    interval,
+                    lo as u64,
+                    (hi + resolution - 1) as u64,
+                );
+                let candles = aggregate(base, resolution);
+                cache.insert(symbol, resolution, (lo, hi), &candles);
+                candles
+            }
+        }
+    }
+
+    pub fn get_min_max_timestamps(&self) -> Option<(i64, i64)> {
+        self.store.get_min_max_timestamps()
+    }
+
+    pub fn snapshot(&self) -> Result<(CandleSnapshot, TradeSnapshot), Error> {
+        let candles = self.store.snapshot()?;
+        Ok((candles, self.trades.read().unwrap().clone()))
+    }
+
+    /// См. `CandleStore::commit`.
+    pub fn commit(&self, checkpoint: &[u8]) -> Result<bool, Error> {
+        self.store.commit(checkpoint)
+    }
+
+    pub fn committed_checkpoint(&self) -> Result<Option<Vec<u8>>, Error> {
+        self.store.committed_checkpoint()
+    }
+
+    pub fn restore(&self, candles: CandleSnapshot, trades: TradeSnapshot) -> Result<(), Error> {
+        self.store.restore(candles)?;
+        *self.trades.write().unwrap() = trades;
+        self.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<(), Error> {
+        self.store.clear()?;
+        self.trades.write().unwrap().clear();
+        self.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn aggregate_trades(&self, symbol: &str, resolution: i64, lo: i64, hi: i64) -> Vec<Candle> {
+        let trades = self.trades.read().unwrap();
+        let Some(trades) = trades.get(symbol) else {
+            return vec![];
+        };
+        let start = trades.partition_point(|t| t.timestamp < lo);
+        let end = trades.partition_point(|t| t.timestamp < hi + resolution);
+        aggregate(
+            trades
+                .range(start..end)
+                .map(|t| Candle::traded(t.timestamp, t.price, t.volume, t.timestamp)),
+            resolution,
+        )
+    }
+}
+
+/// Из чего собирается разрешение.
+enum Source {
+    /// Серия стора с этим интервалом.
+    Series(u64),
+    Trades,
+}
+
+impl Source {
+    fn for_resolution(resolution: u64) -> Option<Self> {
+        if resolution == 0 {
+            None
+        } else if resolution % DAY_INTERVAL == 0 {
+            Some(Source::Series(DAY_INTERVAL))
+        } else if resolution % BASE_INTERVAL == 0 {
+            Some(Source::Series(BASE_INTERVAL))
+        } else if BASE_INTERVAL % resolution == 0 {
+            Some(Source::Trades)
+        } else {
+            None
+        }
+    }
+}
+
+fn bucket_start(time: i64, resolution: i64) -> i64 {
+    time - time.rem_euclid(resolution)
+}
+
+/// Сворачивает свечи (или сделки) по возрастанию времени в свечи `resolution`,
+/// дозаполняя пустые периоды между ними ценой закрытия.
+fn aggregate(candles: impl IntoIterator<Item = Candle>, resolution: i64) -> Vec<Candle> {
+    let mut result: Vec<Candle> = Vec::new();
+    for candle in candles {
+        let bucket = bucket_start(candle.timestamp.timestamp(), resolution);
+        if let Some(last) = result.last_mut() {
+            let last_bucket = last.timestamp.timestamp();
+            if last_bucket == bucket {
+                last.extend(&candle);
+                continue;
+            }
+            let close = last.close;
+            let mut missing = last_bucket + resolution;
+            while missing < bucket {
+                result.push(Candle::flat(missing, close, 0));
+                missing += resolution;
+            }
+        }
+        let mut first = Candle::flat(bucket, candle.open, 0);
+        first.extend(&candle);
+        result.push(first);
+    }
+    result
+}
+
+/// Собранная серия и диапазон начал свечей, посчитанный полностью.
+struct CachedSeries {
+    candles: BTreeMap<i64, Candle>,
+    covered: (i64, i64),
+    used: u64,
+}
+
+/// Кэш собранных серий. Запись сделки сбрасывает свечи начиная с периода,
+/// который она затронула; при переполнении вытесняется давно не читанная серия.
+struct AggregateCache {
+    series: HashMap<(String, i64), CachedSeries>,
+    capacity: usize,
+    tick: u64,
+}
+
+impl AggregateCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            series: HashMap::new(),
+            capacity,
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, symbol: &str, resolution: i64, lo: i64, hi: i64) -> Option<Vec<Candle>> {
+        self.tick += 1;
+        let cached = self.series.get_mut(&(symbol.to_string(), resolution))?;
+        if cached.covered.0 > lo || cached.covered.1 < hi {
+            return None;
+        }
+        cached.used = self.tick;
+        Some(
+            cached
+                .candles
+                .range(lo..=hi)
+                .map(|(_, c)| c.clone())
+                .collect(),
+        )
+    }
+
+    fn insert(&mut self, symbol: &str, resolution: i64, (lo, hi): (i64, i64), candles: &[Candle]) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        let cached = self
+            .series
+            .entry((symbol.to_string(), resolution))
+            .or_insert_with(|| CachedSeries {
+                candles: BTreeMap::new(),
+                covered: (lo, hi),
+                used: 0,
+            });
+        let (cached_lo, cached_hi) = cached.covered;
+        if lo > cached_hi + resolution || hi + resolution < cached_lo {
+            // Не стыкуется с посчитанным диапазоном, держим только новый
+            cached.candles.clear();
+            cached.covered = (lo, hi);
+        } else {
+            cached.covered = (cached_lo.min(lo), cached_hi.max(hi));
+        }
+        for candle in candles {
+            cached
+                .candles
+                .insert(candle.timestamp.timestamp(), candle.clone());
+        }
+        cached.used = self.tick;
+
+        if self.series.len() > self.capacity {
+            let oldest = self
+                .series
+                .iter()
+                .min_by_key(|(_, cached)| cached.used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                self.series.remove(&key);
+            }
+        }
+    }
+
+    /// Сбрасывает свечи `symbol`, начиная с периода, в который попадает
+    /// `from` для разрешений из базовой серии и `day_from` для дневных.
+    fn invalidate(&mut self, symbol: &str, from: i64, day_from: i64) {
+        self.series.retain(|(cached_symbol, resolution), cached| {
+            if cached_symbol != symbol {
+                return true;
+            }
+            let from = match Source::for_resolution(*resolution as u64) {
+                Some(Source::Series(DAY_INTERVAL)) => day_from,
+                _ => from,
+            };
+            let bucket = bucket_start(from, *resolution);
+            cached.candles.split_off(&bucket);
+            cached.covered.1 = cached.covered.1.min(bucket - resolution);
+            cached.covered.0 <= cached.covered.1
+        });
+    }
+
+    fn clear(&mut self) {
+        self.series.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::candles::InMemoryCandleStore;
+    use crate::storage::retention::Retention;
+
+    const SYMBOL: &str = "BASE/QUOTE";
+
+    fn timeframes() -> Timeframes {
+        Timeframes::from_env(Arc::new(InMemoryCandleStore::new(Retention::Forever)))
+    }
+
+    /// Минутная свеча со сделками на всю минуту.
+    fn minute(start: i64, open: u128, high: u128, low: u128, close: u128, volume: u128) -> Candle {
+        Candle {
+            open,
+            high,
+            low,
+            close,
+            volume,
+            first_trade: Some(start),
+            last_trade: Some(start + 59),
+            ..Candle::flat(start, open, 0)
+        }
+    }
+
+    fn ohlcv(candle: &Candle) -> (i64, u128, u128, u128, u128, u128) {
+        (
+            candle.timestamp.timestamp(),
+            candle.open,
+            candle.high,
+            candle.low,
+            candle.close,
+            candle.volume,
+        )
+    }
+
+    #[test]
+    fn aggregates_base_candles_into_larger_resolutions() {
+        let base = vec![
+            minute(0, 10, 15, 8, 12, 1),
+            minute(60, 12, 20, 11, 18, 2),
+            minute(360, 18, 19, 5, 6, 3),
+            minute(3600, 7, 7, 7, 7, 4),
+            minute(86400, 9, 9, 9, 9, 5),
+        ];
+
+        let five = aggregate(base.clone(), 300);
+        assert_eq!(five.len(), 86400 / 300 + 1);
+        assert_eq!(ohlcv(&five[0]), (0, 10, 20, 8, 18, 3));
+        assert_eq!((five[0].first_trade, five[0].last_trade), (Some(0), Some(119)));
+        assert_eq!(ohlcv(&five[1]), (300, 18, 19, 5, 6, 3));
+        assert_eq!(five[2], Candle::flat(600, 6, 0));
+        assert_eq!(ohlcv(&five[12]), (3600, 7, 7, 7, 7, 4));
+
+        let hour = aggregate(base.clone(), 3600);
+        assert_eq!(hour.len(), 25);
+        assert_eq!(ohlcv(&hour[0]), (0, 10, 20, 5, 6, 6));
+        assert_eq!((hour[0].first_trade, hour[0].last_trade), (Some(0), Some(419)));
+        assert_eq!(ohlcv(&hour[1]), (3600, 7, 7, 7, 7, 4));
+        assert_eq!(hour[2], Candle::flat(7200, 7, 0));
+
+        let day = aggregate(base, 86400);
+        assert_eq!(day.len(), 2);
+        assert_eq!(ohlcv(&day[0]), (0, 10, 20, 5, 7, 10));
+        assert_eq!((day[0].first_trade, day[0].last_trade), (Some(0), Some(3659)));
+        assert_eq!(ohlcv(&day[1]), (86400, 9, 9, 9, 9, 5));
+    }
+
+    #[test]
+    fn builds_sub_minute_resolutions_from_raw_trades() {
+        let timeframes = timeframes();
+        for (price, volume, time) in [(100, 1, 3), (110, 2, 7), (90, 1, 12), (95, 1, 31)] {
+            timeframes.add_trade(SYMBOL, price, volume, time).unwrap();
+        }
+
+        let candles = timeframes.get_candles_in_time_range_secs(SYMBOL, 10, 0, 39);
+        let candles: Vec<_> = candles.iter().map(ohlcv).collect();
+        assert_eq!(
+            candles,
+            vec![
+                (0, 100, 110, 100, 110, 3),
+                (10, 90, 90, 90, 90, 1),
+                (20, 90, 90, 90, 90, 0),
+                (30, 95, 95, 95, 95, 1),
+            ]
+        );
+        assert!(timeframes.get_candles_in_time_range_secs(SYMBOL, 7, 0, 39).is_empty());
+    }
+
+    #[test]
+    fn late_trade_invalidates_cached_aggregates() {
+        let timeframes = timeframes();
+        timeframes.add_trade(SYMBOL, 100, 1, 10).unwrap();
+        timeframes.add_trade(SYMBOL, 200, 1, 400).unwrap();
+
+        let candles = timeframes.get_candles_in_time_range_secs(SYMBOL, 300, 300, 600);
+        assert_eq!(candles.iter().map(ohlcv).collect::<Vec<_>>(), vec![(300, 100, 200, 100, 200, 1)]);
+        // Закэширован только [300, 600], более широкий запрос собирается заново
+        let candles = timeframes.get_candles_in_time_range_secs(SYMBOL, 300, 0, 600);
+        assert_eq!(
+            candles.iter().map(ohlcv).collect::<Vec<_>>(),
+            vec![(0, 100, 100, 100, 100, 1), (300, 100, 200, 100, 200, 1)]
+        );
+
+        timeframes.add_trade(SYMBOL, 150, 2, 70).unwrap();
+        let candles = timeframes.get_candles_in_time_range_secs(SYMBOL, 300, 0, 600);
+        assert_eq!(
+            candles.iter().map(ohlcv).collect::<Vec<_>>(),
+            vec![(0, 100, 150, 100, 150, 3), (300, 150, 200, 150, 200, 1)]
+        );
+    }
+
+    #[test]
+    fn cache_serves_only_covered_ranges() {
+        let mut cache = AggregateCache::new(4);
+        let candles = vec![Candle::flat(0, 1, 0), Candle::flat(300, 2, 0), Candle::flat(600, 3, 0)];
+        cache.insert(SYMBOL, 300, (300, 600), &candles[1..]);
+
+        assert!(cache.get(SYMBOL, 300, 0, 600).is_none());
+        assert!(cache.get(SYMBOL, 300, 300, 900).is_none());
+        assert_eq!(cache.get(SYMBOL, 300, 300, 600).unwrap().len(), 2);
+
+        cache.insert(SYMBOL, 300, (0, 0), &candles[..1]);
+        assert_eq!(cache.get(SYMBOL, 300, 0, 600).unwrap().len(), 3);
+
+        // Сделка в периоде 300 сбрасывает его и все, что после
+        cache.invalidate(SYMBOL, 450, i64::MAX);
+        assert_eq!(cache.get(SYMBOL, 300, 0, 0).unwrap().len(), 1);
+        assert!(cache.get(SYMBOL, 300, 0, 300).is_none());
+        cache.invalidate(SYMBOL, 0, i64::MAX);
+        assert!(cache.get(SYMBOL, 300, 0, 0).is_none());
+    }
+
+    #[test]
+    fn daily_history_survives_minute_eviction() {
+        let store = Arc::new(InMemoryCandleStore::new(Retention::Count(10)));
+        let timeframes = Timeframes::from_env(store.clone());
+        for (day, price) in [(0, 100), (1, 110), (2, 90)] {
+            timeframes.add_trade(SYMBOL, price, 1, day * 86400 + 30).unwrap();
+            timeframes.add_trade(SYMBOL, price + 5, 1, day * 86400 + 3600).unwrap();
+        }
+        assert!(store
+            .get_candles_in_time_range_secs(SYMBOL, BASE_INTERVAL, 0, 86400)
+            .is_empty());
+
+        let days = timeframes.get_candles_in_time_range_secs(SYMBOL, 86400, 0, 2 * 86400);
+        assert_eq!(
+            days.iter().map(ohlcv).collect::<Vec<_>>(),
+            vec![
+                (0, 100, 105, 100, 105, 2),
+                (86400, 110, 115, 110, 115, 2),
+                (2 * 86400, 90, 95, 90, 95, 2),
+            ]
+        );
+        let weeks = timeframes.get_candles_in_time_range_secs(SYMBOL, 7 * 86400, 0, 2 * 86400);
+        assert_eq!(weeks.iter().map(ohlcv).collect::<Vec<_>>(), vec![(0, 100, 115, 90, 95, 6)]);
+    }
+
+    #[test]
+    fn rebuilds_missing_daily_series_from_minutes() {
+        let store = Arc::new(InMemoryCandleStore::new(Retention::Forever));
+        store
+            .put_candles(
+                SYMBOL,
+                BASE_INTERVAL,
+                vec![minute(60, 10, 12, 9, 11, 1), minute(86400 + 60, 11, 11, 11, 11, 2)],
+            )
+            .unwrap();
+        let timeframes = Timeframes::from_env(store);
+
+        timeframes.rebuild_daily([SYMBOL]).unwrap();
+        let days = timeframes.get_candles_in_time_range_secs(SYMBOL, 86400, 0, 86400);
+        assert_eq!(
+            days.iter().map(ohlcv).collect::<Vec<_>>(),
+            vec![(0, 10, 12, 9, 11, 1), (86400, 11, 11, 11, 11, 2)]
+        );
+    }
+}
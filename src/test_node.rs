@@ -0,0 +1,118 @@
+//! Spark рынок на in-process ноде `fuel-core-lib` для тестов.
+
+use fuels::accounts::provider::Provider;
+use fuels::accounts::wallet::WalletUnlocked;
+use fuels::test_helpers::{launch_custom_provider_and_get_wallets, AssetConfig, WalletsConfig};
+use fuels::tx::Receipt;
+use fuels::types::{AssetId, ContractId};
+use spark_market_sdk::{OrderType as SparkOrderType, SparkMarketContract};
+
+use crate::config::markets::Markets;
+
+pub const BASE_DECIMALS: u32 = 9;
+pub const QUOTE_DECIMALS: u32 = 6;
+pub const PRICE_DECIMALS: u32 = 9;
+
+/// Задеплоенный рынок и кошельки с балансами базового и котируемого ассетов.
+pub struct TestMarket {
+    pub provider: Provider,
+    pub contract_id: ContractId,
+    pub base_asset: AssetId,
+    pub quote_asset: AssetId,
+    contract: SparkMarketContract,
+    seller: WalletUnlocked,
+    buyer: WalletUnlocked,
+}
+
+impl TestMarket {
+    pub async fn deploy() -> Self {
+        let base_asset = AssetId::new([1; 32]);
+        let quote_asset = AssetId::new([2; 32]);
+        let assets = [AssetId::zeroed(), base_asset, quote_asset]
+            .into_iter()
+            .map(|id| AssetConfig {
+                id,
+                num_coins: 1,
+                coin_amount: u64::MAX / 4,
+            })
+            .collect();
+        let mut wallets = launch_custom_provider_and_get_wallets(
+            WalletsConfig::new_multiple_assets(3, assets),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let buyer = wallets.pop().unwrap();
+        let seller = wallets.pop().unwrap();
+        let owner = wallets.pop().unwrap();
+        let provider = owner.provider().unwrap().clone();
+
+        let contract = SparkMarketContract::deploy(
+            base_asset,
+            BASE_DECIMALS,
+            quote_asset,
+            QUOTE_DECIMALS,
+            owner,
+            PRICE_DECIMALS,
+            1,
+        )
+        .await
+        .unwrap();
+
+        Self {
+            provider,
+            contract_id: ContractId::from(contract.contract_id()),
+            base_asset,
+            quote_asset,
+            contract,
+            seller,
+            buyer,
+        }
+    }
+
+    /// Конфиг с одним этим рынком.
+    pub fn markets(&self, symbol: Option<&str>) -> Markets {
+        let symbol = symbol
+            .map(|symbol| format!("symbol = \"{}\"\n", symbol))
+            .unwrap_or_default();
+        Markets::from_toml(&format!(
+            "[[markets]]\nid = \"0x{}\"\n{}",
+            hex::encode(self.contract_id),
+            symbol
+        ))
+        .unwrap()
+    }
+
+    /// Выставляет встречные ордера `amount` по `price` и сводит их.
+    /// Возвращает квитанции транзакции сведения.
+    pub async fn trade(&self, amount: u64, price: u64) -> Vec<Receipt> {
+        let seller = SparkMarketContract::new(self.contract_id, self.seller.clone()).await;
+        seller.deposit(amount, self.base_asset).await.unwrap();
+        let sell = seller
+            .open_order(amount, SparkOrderType::Sell, price)
+            .await
+            .unwrap()
+            .value;
+
+        // С запасом на комиссии
+        let quote = amount as u128 * price as u128
+            / 10u128.pow(BASE_DECIMALS + PRICE_DECIMALS - QUOTE_DECIMALS);
+        let buyer = SparkMarketContract::new(self.contract_id, self.buyer.clone()).await;
+        buyer
+            .deposit(quote as u64 * 2, self.quote_asset)
+            .await
+            .unwrap();
+        let buy = buyer
+            .open_order(amount, SparkOrderType::Buy, price)
+            .await
+            .unwrap()
+            .value;
+
+        self.contract
+            .match_order_pair(sell, buy)
+            .await
+            .unwrap()
+            .receipts
+    }
+}
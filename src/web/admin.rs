@@ -0,0 +1,40 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+
+use crate::config::env::ev;
+
+/// Доступ к `/admin/*`: заголовок `Authorization: Bearer <ADMIN_TOKEN>`.
+/// Без заданного `ADMIN_TOKEN` админские эндпоинты закрыты.
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Ok(expected) = ev("ADMIN_TOKEN") else {
+            return Outcome::Error((Status::Forbidden, ()));
+        };
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if !expected.is_empty() && token == expected => Outcome::Success(AdminToken),
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for AdminToken {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
@@ -1,8 +1,13 @@
-use crate::indexer::spot_order::OrderType;
-use crate::storage::order_book::OrderBook;
+use crate::config::markets::{format_units, Market, Markets};
+use crate::indexer::spot_order::{OrderType, SpotOrder};
+use crate::indexer::source::Indexer;
+use crate::storage::candles::Candle;
+use crate::storage::order_book::{IndexerOrderBooks, OrderBook};
+use crate::storage::timeframes::Timeframes;
 use async_graphql::{Context, Object, SimpleObject};
 use std::sync::Arc;
 
+/// Ордер в человеческих единицах: `amount` в базовом ассете, `price` в котируемом.
 #[derive(SimpleObject, Clone)]
 struct Order {
     id: String,
@@ -15,48 +20,109 @@ struct Order {
     status: Option<String>,
 }
 
+impl Order {
+    fn new(order: SpotOrder, market: &Market) -> Self {
+        Self {
+            id: order.id,
+            user: order.user,
+            asset: order.asset,
+            amount: format_units(order.amount, market.base_decimals),
+            price: format_units(order.price, market.price_decimals),
+            timestamp: order.timestamp,
+            order_type: format!("{:?}", order.order_type),
+            status: order.status.map(|s| format!("{:?}", s)),
+        }
+    }
+}
+
+/// Свеча в человеческих единицах: цены в котируемом ассете, объем в базовом.
+#[derive(SimpleObject, Clone)]
+struct CandleData {
+    timestamp: i64,
+    open: String,
+    high: String,
+    low: String,
+    close: String,
+    volume: String,
+}
+
+impl CandleData {
+    fn new(candle: Candle, market: &Market) -> Self {
+        Self {
+            timestamp: candle.timestamp.timestamp(),
+            open: format_units(candle.open, market.price_decimals),
+            high: format_units(candle.high, market.price_decimals),
+            low: format_units(candle.low, market.price_decimals),
+            close: format_units(candle.close, market.price_decimals),
+            volume: format_units(candle.volume, market.base_decimals),
+        }
+    }
+}
+
 pub struct Query;
 
 #[Object]
 impl Query {
-    pub async fn buy_orders(&self, ctx: &Context<'_>) -> Vec<Order> {
-        let order_book = ctx.data::<Arc<OrderBook>>().unwrap();
+    pub async fn buy_orders(
+        &self,
+        ctx: &Context<'_>,
+        market: String,
+        indexer: Option<String>,
+    ) -> Vec<Order> {
+        let Some((config, order_book)) = market_order_book(ctx, &market, indexer.as_deref()) else {
+            return vec![];
+        };
         let buy_orders = order_book.get_orders_in_range(0, u128::MAX, OrderType::Buy);
         buy_orders
             .into_iter()
-            .map(|order| Order {
-                id: order.id,
-                user: order.user,
-                asset: order.asset,
-                amount: order.amount.to_string(),
-                price: order.price.to_string(),
-                timestamp: order.timestamp,
-                order_type: "Buy".to_string(),
-                status: order.status.map(|s| format!("{:?}", s)),
-            })
+            .map(|order| Order::new(order, &config))
             .collect()
     }
 
-    pub async fn sell_orders(&self, ctx: &Context<'_>) -> Vec<Order> {
-        let order_book = ctx.data::<Arc<OrderBook>>().unwrap();
+    pub async fn sell_orders(
+        &self,
+        ctx: &Context<'_>,
+        market: String,
+        indexer: Option<String>,
+    ) -> Vec<Order> {
+        let Some((config, order_book)) = market_order_book(ctx, &market, indexer.as_deref()) else {
+            return vec![];
+        };
         let sell_orders = order_book.get_orders_in_range(0, u128::MAX, OrderType::Sell);
         sell_orders
             .into_iter()
-            .map(|order| Order {
-                id: order.id,
-                user: order.user,
-                asset: order.asset,
-                amount: order.amount.to_string(),
-                price: order.price.to_string(),
-                timestamp: order.timestamp,
-                order_type: "Sell".to_string(),
-                status: order.status.map(|s| format!("{:?}", s)),
-            })
+            .map(|order| Order::new(order, &config))
+            .collect()
+    }
+
+    /// Свечи разрешения `resolution` (секунды) с началом в `[from, to]`.
+    pub async fn candles(
+        &self,
+        ctx: &Context<'_>,
+        market: String,
+        resolution: u64,
+        from: u64,
+        to: u64,
+    ) -> Vec<CandleData> {
+        let Some(config) = ctx.data::<Arc<Markets>>().unwrap().by_symbol(&market) else {
+            return vec![];
+        };
+        ctx.data::<Arc<Timeframes>>()
+            .unwrap()
+            .get_candles_in_time_range_secs(&market, resolution, from, to)
+            .into_iter()
+            .map(|candle| CandleData::new(candle, config))
             .collect()
     }
 
-    pub async fn spread(&self, ctx: &Context<'_>) -> Option<String> {
-        let order_book = ctx.data::<Arc<OrderBook>>().unwrap();
+    /// Спред в единицах котируемого ассета.
+    pub async fn spread(
+        &self,
+        ctx: &Context<'_>,
+        market: String,
+        indexer: Option<String>,
+    ) -> Option<String> {
+        let (config, order_book) = market_order_book(ctx, &market, indexer.as_deref())?;
         let buy_orders = order_book.get_orders_in_range(0, u128::MAX, OrderType::Buy);
         let sell_orders = order_book.get_orders_in_range(0, u128::MAX, OrderType::Sell);
 
@@ -64,9 +130,29 @@ impl Query {
         let min_sell_price = sell_orders.iter().map(|o| o.price).min();
 
         if let (Some(max_buy), Some(min_sell)) = (max_buy_price, min_sell_price) {
-            Some((min_sell as i128 - max_buy as i128).to_string())
+            let spread = format_units(min_sell.abs_diff(max_buy), config.price_decimals);
+            if min_sell < max_buy {
+                Some(format!("-{}", spread))
+            } else {
+                Some(spread)
+            }
         } else {
             None
         }
     }
 }
+
+/// Рынок и его книга у индексатора `indexer`, по умолчанию у основного.
+fn market_order_book(
+    ctx: &Context<'_>,
+    market: &str,
+    indexer: Option<&str>,
+) -> Option<(Market, Arc<OrderBook>)> {
+    let indexer = match indexer {
+        Some(name) => Some(name.parse::<Indexer>().ok()?),
+        None => None,
+    };
+    let config = ctx.data::<Arc<Markets>>().unwrap().by_symbol(market)?.clone();
+    let order_books = ctx.data::<Arc<IndexerOrderBooks>>().unwrap();
+    Some((config, order_books.get(indexer)?.get(market)?))
+}
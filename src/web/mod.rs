@@ -1,3 +1,4 @@
+pub mod admin;
 pub mod graphql;
 pub mod routes;
 pub mod server;
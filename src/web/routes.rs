@@ -8,15 +8,22 @@ use log::{info, warn};
 use rocket::request::FromParam;
 use rocket::response::content;
 use rocket::serde::json::Json;
-use rocket::{get, routes, Route, State};
+use rocket::{get, post, routes, Route, State};
 use rocket_okapi::swagger_ui::SwaggerUIConfig;
 use rocket_okapi::{openapi, openapi_get_routes, JsonSchema};
-use serde::{Deserialize, Serialize};
-
+use serde::Serialize;
+
+use crate::config::market_metadata::MarketMetadata;
+use crate::config::markets::{format_units, Market, Markets};
+use crate::indexer::consistency::{ConsistencyChecker, ConsistencyReport};
+use crate::indexer::dead_letter::{DeadLetter, DeadLetterReprocessor, ReprocessReport};
+use crate::indexer::source::Indexer;
+use crate::indexer::supervisor::{IndexerStatuses, TaskStatus};
 use crate::indexer::spot_order::{OrderType, SpotOrder};
-use crate::storage::candles::CandleStore;
-use crate::storage::order_book::OrderBook;
+use crate::storage::timeframes::Timeframes;
+use crate::storage::order_book::IndexerOrderBooks;
 
+use super::admin::AdminToken;
 use super::graphql::Query;
 
 #[derive(Serialize, JsonSchema)]
@@ -24,37 +31,11 @@ pub struct OrdersResponse {
     pub orders: Vec<SpotOrder>,
 }
 
-#[derive(Serialize, Deserialize, Clone, JsonSchema)]
-pub enum Indexer {
-    Envio,
-    Subsquid,
-    Pangea,
-}
-
-impl Indexer {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Indexer::Envio => "envio",
-            Indexer::Subsquid => "subsquid",
-            Indexer::Pangea => "superchain",
-        }
-    }
-
-    pub fn all() -> Vec<Indexer> {
-        vec![Indexer::Envio, Indexer::Subsquid, Indexer::Pangea]
-    }
-}
-
 impl<'r> FromParam<'r> for Indexer {
     type Error = &'r str;
 
     fn from_param(param: &'r str) -> Result<Self, Self::Error> {
-        match param {
-            "Envio" => Ok(Indexer::Envio),
-            "Subsquid" => Ok(Indexer::Subsquid),
-            "Pangea" => Ok(Indexer::Pangea),
-            _ => Err(param),
-        }
+        param.parse().map_err(|_| param)
     }
 }
 
@@ -67,12 +48,41 @@ pub struct AdvancedChartResponse {
     l: Vec<f64>,          // Минимум
     c: Vec<f64>,          // Закрытие
     v: Vec<f64>,          // Объём
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errmsg: Option<String>, // Причина ошибки при статусе "error"
+}
+
+impl AdvancedChartResponse {
+    fn empty(status: &str, errmsg: Option<String>) -> Self {
+        Self {
+            s: status.to_string(),
+            t: vec![],
+            o: vec![],
+            h: vec![],
+            l: vec![],
+            c: vec![],
+            v: vec![],
+            errmsg,
+        }
+    }
+}
+
+/// Тот же формат, но с точными десятичными строками вместо f64.
+#[derive(serde::Serialize, JsonSchema)]
+pub struct CandlesResponse {
+    s: String,
+    t: Vec<u64>,
+    o: Vec<String>,
+    h: Vec<String>,
+    l: Vec<String>,
+    c: Vec<String>,
+    v: Vec<String>,
 }
 
 #[openapi]
 #[get("/timestamps")]
-fn get_timestamps(candle_store: &State<Arc<CandleStore>>) -> Json<Option<(i64, i64)>> {
-    let min_max = candle_store.get_min_max_timestamps();
+fn get_timestamps(timeframes: &State<Arc<Timeframes>>) -> Json<Option<(i64, i64)>> {
+    let min_max = timeframes.get_min_max_timestamps();
     Json(min_max)
 }
 
@@ -134,16 +144,7 @@ fn get_config() -> Json<ConfigResponse> {
                 value: "crypto".to_string(),
             },
         ],
-        supported_resolutions: vec![
-            "1".to_string(),
-            "5".to_string(),
-            "15".to_string(),
-            "30".to_string(),
-            "60".to_string(),
-            "D".to_string(),
-            "W".to_string(),
-            "M".to_string(),
-        ],
+        supported_resolutions: supported_resolutions(),
     };
 
     Json(config)
@@ -167,7 +168,8 @@ pub struct SymbolInfo {
     pub exchange: String,
     pub timezone: String,
     pub minmov: u32,
-    pub pricescale: u32,
+    pub pricescale: u64,
+    pub volume_precision: u32,
     pub session: String,
     pub has_intraday: bool,
     pub has_daily: bool,
@@ -177,160 +179,236 @@ pub struct SymbolInfo {
 }
 
 
-#[openapi]
-#[get("/symbols?<symbol>")]
-fn get_symbols(symbol: Option<String>) -> Json<SymbolInfo> {
-    let symbol = symbol.unwrap_or_else(|| "AAPL".to_string()); // Используем `AAPL` по умолчанию
-
-    let symbols = vec![
-        SymbolInfo {
-            symbol: "BTC/USD".to_string(),
-            ticker: "BTC/USD".to_string(),
-            name: "Bitcoin / US Dollar".to_string(),
-            description: "BTC to USD".to_string(),
-            type_: "crypto".to_string(),
-            exchange: "CryptoExchange".to_string(),
-            timezone: "Etc/UTC".to_string(),
-            minmov: 1,
-            pricescale: 100,
-            session: "24x7".to_string(),
-            has_intraday: true,
-            has_daily: true,
-            supported_resolutions: vec![
-                "1".to_string(),
-                "5".to_string(),
-                "15".to_string(),
-                "30".to_string(),
-                "60".to_string(),
-                "D".to_string(),
-                "W".to_string(),
-                "M".to_string(),
-            ],
-            intraday_multipliers: vec![
-                "1".to_string(),
-                "5".to_string(),
-                "15".to_string(),
-                "30".to_string(),
-                "60".to_string(),
-            ],
-            format: "price".to_string(),
-        },
-        SymbolInfo {
-            symbol: "ETH/USD".to_string(),
-            ticker: "ETH/USD".to_string(),
-            name: "Ethereum / US Dollar".to_string(),
-            description: "ETH to USD".to_string(),
-            type_: "crypto".to_string(),
-            exchange: "CryptoExchange".to_string(),
-            timezone: "Etc/UTC".to_string(),
-            minmov: 1,
-            pricescale: 100,
-            session: "24x7".to_string(),
-            has_intraday: true,
-            has_daily: true,
-            supported_resolutions: vec![
-                "1".to_string(),
-                "5".to_string(),
-                "15".to_string(),
-                "30".to_string(),
-                "60".to_string(),
-                "D".to_string(),
-                "W".to_string(),
-                "M".to_string(),
-            ],
-            intraday_multipliers: vec![
-                "1".to_string(),
-                "5".to_string(),
-                "15".to_string(),
-                "30".to_string(),
-                "60".to_string(),
-            ],
-            format: "price".to_string(),
-        },
-        // Символ для AAPL
-        SymbolInfo {
-            symbol: "AAPL".to_string(),
-            ticker: "AAPL".to_string(),
-            name: "Apple Inc.".to_string(),
-            description: "Apple Stock".to_string(),
-            type_: "stock".to_string(),
-            exchange: "NASDAQ".to_string(),
-            timezone: "America/New_York".to_string(),
-            minmov: 1,
-            pricescale: 100,
-            session: "0930-1600".to_string(),
-            has_intraday: true,
-            has_daily: true,
-            supported_resolutions: vec![
-                "1".to_string(),
-                "5".to_string(),
-                "15".to_string(),
-                "30".to_string(),
-                "60".to_string(),
-                "D".to_string(),
-                "W".to_string(),
-                "M".to_string(),
-            ],
-            intraday_multipliers: vec![
-                "1".to_string(),
-                "5".to_string(),
-                "15".to_string(),
-                "30".to_string(),
-                "60".to_string(),
-            ],
-            format: "price".to_string(),
-        },
-    ];
-
-    // Поиск символа
-    let result = symbols.into_iter().find(|s| s.symbol == symbol).unwrap_or_else(|| SymbolInfo {
-        symbol: "AAPL".to_string(),
-        ticker: "AAPL".to_string(),
-        name: "Apple Inc.".to_string(),
-        description: "Apple Stock".to_string(),
-        type_: "stock".to_string(),
-        exchange: "NASDAQ".to_string(),
-        timezone: "America/New_York".to_string(),
+/// Месячного разрешения нет: месяцы разной длины и не ложатся на сетку минутных свечей.
+fn supported_resolutions() -> Vec<String> {
+    vec!["1", "5", "15", "30", "60", "120", "240", "720", "D", "W"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+fn market_symbol_info(market: &Market) -> SymbolInfo {
+    let description = match &market.metadata {
+        Some(metadata) => format!(
+            "Spark market {:?}: {} / {}",
+            market.id, metadata.base_asset, metadata.quote_asset
+        ),
+        None => format!("Spark market {:?}", market.id),
+    };
+    SymbolInfo {
+        symbol: market.symbol.clone(),
+        ticker: market.symbol.clone(),
+        name: market.symbol.clone(),
+        description,
+        type_: "crypto".to_string(),
+        exchange: "Spark".to_string(),
+        timezone: "Etc/UTC".to_string(),
         minmov: 1,
-        pricescale: 100,
-        session: "0930-1600".to_string(),
+        pricescale: market.pricescale(),
+        volume_precision: market.base_decimals,
+        session: "24x7".to_string(),
         has_intraday: true,
         has_daily: true,
-        supported_resolutions: vec![
-            "1".to_string(),
-            "5".to_string(),
-            "15".to_string(),
-            "30".to_string(),
-            "60".to_string(),
-            "D".to_string(),
-            "W".to_string(),
-            "M".to_string(),
-        ],
-        intraday_multipliers: vec![
-            "1".to_string(),
-            "5".to_string(),
-            "15".to_string(),
-            "30".to_string(),
-            "60".to_string(),
-        ],
+        supported_resolutions: supported_resolutions(),
+        intraday_multipliers: vec!["1", "5", "15", "30", "60", "120", "240", "720"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
         format: "price".to_string(),
-    });
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct MarketInfo {
+    pub id: String,
+    pub symbol: String,
+    pub base_decimals: u32,
+    pub quote_decimals: u32,
+    pub price_decimals: u32,
+    /// Ассеты и комиссии из контракта, если метаданные загружены.
+    pub metadata: Option<MarketMetadata>,
+}
+
+/// Все индексируемые рынки с их параметрами.
+#[openapi]
+#[get("/markets")]
+fn get_markets(markets: &State<Arc<Markets>>) -> Json<Vec<MarketInfo>> {
+    Json(
+        markets
+            .iter()
+            .map(|market| MarketInfo {
+                id: format!("{:?}", market.id),
+                symbol: market.symbol.clone(),
+                base_decimals: market.base_decimals,
+                quote_decimals: market.quote_decimals,
+                price_decimals: market.price_decimals,
+                metadata: market.metadata.clone(),
+            })
+            .collect(),
+    )
+}
+
+#[openapi]
+#[get("/symbols?<symbol>")]
+fn get_symbols(markets: &State<Arc<Markets>>, symbol: Option<String>) -> Option<Json<SymbolInfo>> {
+    // Без параметра отдаем первый сконфигурированный рынок
+    let market = match symbol {
+        Some(symbol) => markets.by_symbol(&symbol),
+        None => markets.iter().next(),
+    }?;
+
+    Some(Json(market_symbol_info(market)))
+}
+
+/// Уровень книги в человеческих единицах: цена в котируемом ассете,
+/// суммарный объем в базовом.
+#[derive(Serialize, JsonSchema)]
+pub struct PriceLevel {
+    pub price: String,
+    pub amount: String,
+    pub orders: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct OrderBookResponse {
+    pub market: String,
+    pub indexer: Indexer,
+    pub buy_orders: Vec<SpotOrder>,
+    pub sell_orders: Vec<SpotOrder>,
+    /// Покупки по убыванию цены.
+    pub bids: Vec<PriceLevel>,
+    /// Продажи по возрастанию цены.
+    pub asks: Vec<PriceLevel>,
+}
+
+/// Сворачивает ордера, отсортированные по цене, в уровни.
+fn price_levels(market: &Market, orders: &[SpotOrder]) -> Vec<PriceLevel> {
+    let mut levels: Vec<(u128, u128, usize)> = Vec::new();
+    for order in orders {
+        match levels.last_mut() {
+            Some((price, amount, count)) if *price == order.price => {
+                *amount += order.amount;
+                *count += 1;
+            }
+            _ => levels.push((order.price, order.amount, 1)),
+        }
+    }
+    levels
+        .into_iter()
+        .map(|(price, amount, orders)| PriceLevel {
+            price: format_units(price, market.price_decimals),
+            amount: format_units(amount, market.base_decimals),
+            orders,
+        })
+        .collect()
+}
+
+#[openapi]
+#[get("/orderbook/<indexer>?<market>")]
+fn get_order_book(
+    order_books: &State<Arc<IndexerOrderBooks>>,
+    markets: &State<Arc<Markets>>,
+    indexer: Indexer,
+    market: String,
+) -> Option<Json<OrderBookResponse>> {
+    let config = markets.by_symbol(&market)?;
+    let order_book = order_books.get(Some(indexer))?.get(&market)?;
+    let buy_orders = order_book.get_orders_in_range(0, u128::MAX, OrderType::Buy);
+    let sell_orders = order_book.get_orders_in_range(0, u128::MAX, OrderType::Sell);
+    let mut bids = price_levels(config, &buy_orders);
+    bids.reverse();
+    let asks = price_levels(config, &sell_orders);
+
+    Some(Json(OrderBookResponse {
+        buy_orders,
+        sell_orders,
+        bids,
+        asks,
+        market,
+        indexer,
+    }))
+}
+
+/// Последний отчет сверки книг ордеров между индексаторами.
+#[openapi]
+#[get("/consistency")]
+fn get_consistency(consistency: &State<Arc<ConsistencyChecker>>) -> Json<Option<ConsistencyReport>> {
+    Json(consistency.latest())
+}
+
+/// Состояние задач индексаторов: синхронизация, реальное время или падение.
+#[openapi]
+#[get("/status")]
+fn get_status(statuses: &State<Arc<IndexerStatuses>>) -> Json<Vec<TaskStatus>> {
+    Json(statuses.all())
+}
+
+/// Содержимое dead-letter очереди. Требует `ADMIN_TOKEN`.
+#[openapi]
+#[get("/admin/dead-letters")]
+fn get_dead_letters(
+    _admin: AdminToken,
+    dead_letters: &State<Arc<DeadLetterReprocessor>>,
+) -> Option<Json<Vec<DeadLetter>>> {
+    match dead_letters.list() {
+        Ok(letters) => Some(Json(letters)),
+        Err(e) => {
+            warn!("Failed to read dead letters: {}", e);
+            None
+        }
+    }
+}
+
+/// Повторно обрабатывает dead-letter очередь, например после исправления парсера.
+/// Требует `ADMIN_TOKEN`.
+#[openapi]
+#[post("/admin/dead-letters/reprocess")]
+async fn reprocess_dead_letters(
+    _admin: AdminToken,
+    dead_letters: &State<Arc<DeadLetterReprocessor>>,
+) -> Option<Json<ReprocessReport>> {
+    match dead_letters.reprocess().await {
+        Ok(report) => Some(Json(report)),
+        Err(e) => {
+            warn!("Failed to reprocess dead letters: {}", e);
+            None
+        }
+    }
+}
 
-    Json(result)
+/// Разрешение TradingView в секундах: число — минуты, суффиксы `S`, `D`, `W` —
+/// секунды, дни и недели (`30S`, `D`, `1W`).
+fn resolution_secs(resolution: &str) -> Option<u64> {
+    let (count, unit) = match resolution.char_indices().last()? {
+        (i, 'S') => (&resolution[..i], 1),
+        (i, 'D') => (&resolution[..i], 86400),
+        (i, 'W') => (&resolution[..i], 604800),
+        _ => (resolution, 60),
+    };
+    let count = if count.is_empty() { 1 } else { count.parse().ok()? };
+    (count > 0).then_some(count * unit)
 }
 
 #[openapi]
 #[get("/history?<symbol>&<resolution>&<from>&<to>")]
 fn get_history(
-    candle_store: &State<Arc<CandleStore>>,
+    timeframes: &State<Arc<Timeframes>>,
+    markets: &State<Arc<Markets>>,
     symbol: Option<String>,
-    resolution: Option<u64>,
+    resolution: Option<String>,
     from: Option<u64>,
     to: Option<u64>,
 ) -> Json<AdvancedChartResponse> {
     // Логируем входящие параметры
     let symbol = symbol.unwrap_or_default();
-    let resolution = resolution.unwrap_or(60);
+    let resolution = resolution.unwrap_or_else(|| "1".to_string());
+    let Some(resolution) = resolution_secs(&resolution) else {
+        warn!("Unsupported /history resolution {}", resolution);
+        return Json(AdvancedChartResponse::empty(
+            "error",
+            Some(format!("Unsupported resolution {}", resolution)),
+        ));
+    };
     let from = from.unwrap_or(0);
     let to = to.unwrap_or(chrono::Utc::now().timestamp() as u64);
 
@@ -339,32 +417,24 @@ fn get_history(
         symbol, resolution, from, to
     );
 
-    // Проверяем данные в CandleStore
-    let candles = candle_store.get_candles_in_time_range(&symbol, resolution, from, to);
+    // Разрешения кроме базового собираются из минутной серии
+    let candles = timeframes.get_candles_in_time_range_secs(&symbol, resolution, from, to);
 
-    if candles.is_empty() {
+    let Some(market) = markets.by_symbol(&symbol).filter(|_| !candles.is_empty()) else {
         warn!(
             "No candles found for symbol={}, resolution={}, from={}, to={}",
             symbol, resolution, from, to
         );
-        return Json(AdvancedChartResponse {
-            s: "no_data".to_string(),
-            t: vec![],
-            o: vec![],
-            h: vec![],
-            l: vec![],
-            c: vec![],
-            v: vec![],
-        });
-    }
+        return Json(AdvancedChartResponse::empty("no_data", None));
+    };
 
-    // Формируем ответ
+    // Формируем ответ. f64 только для TradingView, точные значения отдает /candles
     let t: Vec<u64> = candles.iter().map(|c| c.timestamp.timestamp() as u64).collect();
-    let o: Vec<f64> = candles.iter().map(|c| c.open).collect();
-    let h: Vec<f64> = candles.iter().map(|c| c.high).collect();
-    let l: Vec<f64> = candles.iter().map(|c| c.low).collect();
-    let c: Vec<f64> = candles.iter().map(|c| c.close).collect();
-    let v: Vec<f64> = candles.iter().map(|c| c.volume).collect();
+    let o: Vec<f64> = candles.iter().map(|c| market.price(c.open)).collect();
+    let h: Vec<f64> = candles.iter().map(|c| market.price(c.high)).collect();
+    let l: Vec<f64> = candles.iter().map(|c| market.price(c.low)).collect();
+    let c: Vec<f64> = candles.iter().map(|c| market.price(c.close)).collect();
+    let v: Vec<f64> = candles.iter().map(|c| market.base_amount(c.volume)).collect();
 
     info!(
         "Returning {} candles for symbol={}, resolution={}, from={}, to={}",
@@ -383,26 +453,26 @@ fn get_history(
         l,
         c,
         v,
+        errmsg: None,
     })
 }
 
+/// Свечи с точными значениями: цены в котируемом ассете, объем в базовом.
 #[openapi]
 #[get("/candles?<symbol>&<interval>&<from>&<to>")]
 pub fn get_candles(
-    candle_store: &State<Arc<CandleStore>>,
+    timeframes: &State<Arc<Timeframes>>,
+    markets: &State<Arc<Markets>>,
     symbol: String,
     interval: u64,
     from: u64,
     to: u64,
-) -> Json<AdvancedChartResponse> {
-    let candles = candle_store
-        .get_candles_in_time_range(&symbol, interval, from, to);
-    //info!("=====================");
-    //info!("candle_store: {:?}", candle_store);
-    //info!("=====================");
-
-    if candles.is_empty() {
-        Json(AdvancedChartResponse {
+) -> Json<CandlesResponse> {
+    let candles = timeframes
+        .get_candles_in_time_range_secs(&symbol, interval, from, to);
+
+    let Some(market) = markets.by_symbol(&symbol).filter(|_| !candles.is_empty()) else {
+        return Json(CandlesResponse {
             s: "no_data".to_string(),
             t: vec![],
             o: vec![],
@@ -410,25 +480,22 @@ pub fn get_candles(
             l: vec![],
             c: vec![],
             v: vec![],
-        })
-    } else {
-        let t: Vec<u64> = candles.iter().map(|c| c.timestamp.timestamp() as u64).collect();
-        let o: Vec<f64> = candles.iter().map(|c| c.open).collect();
-        let h: Vec<f64> = candles.iter().map(|c| c.high).collect();
-        let l: Vec<f64> = candles.iter().map(|c| c.low).collect();
-        let c: Vec<f64> = candles.iter().map(|c| c.close).collect();
-        let v: Vec<f64> = candles.iter().map(|c| c.volume).collect();
-
-        Json(AdvancedChartResponse {
-            s: "ok".to_string(),
-            t,
-            o,
-            h,
-            l,
-            c,
-            v,
-        })
-    }
+        });
+    };
+
+    let price = |raw| format_units(raw, market.price_decimals);
+    Json(CandlesResponse {
+        s: "ok".to_string(),
+        t: candles.iter().map(|c| c.timestamp.timestamp() as u64).collect(),
+        o: candles.iter().map(|c| price(c.open)).collect(),
+        h: candles.iter().map(|c| price(c.high)).collect(),
+        l: candles.iter().map(|c| price(c.low)).collect(),
+        c: candles.iter().map(|c| price(c.close)).collect(),
+        v: candles
+            .iter()
+            .map(|c| format_units(c.volume, market.base_decimals))
+            .collect(),
+    })
 }
 
 
@@ -453,6 +520,12 @@ pub fn get_routes() -> Vec<Route> {
         get_config,
         get_time,
         get_symbols,
+        get_markets,
+        get_order_book,
+        get_consistency,
+        get_status,
+        get_dead_letters,
+        reprocess_dead_letters,
         get_candles,
         get_timestamps,
         get_history
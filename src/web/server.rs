@@ -2,8 +2,10 @@ use std::path::Path;
 use std::sync::Arc;
 use std::net::Ipv4Addr;
 
-use crate::storage::candles::CandleStore;
-use crate::storage::order_book::OrderBook;
+use crate::config::markets::Markets;
+use crate::indexer::consistency::ConsistencyChecker;
+use crate::indexer::pipeline::Indexers;
+use crate::storage::timeframes::Timeframes;
 use crate::web::routes::{get_docs, get_routes};
 use async_graphql::Schema;
 use rocket::fairing::{Fairing, Info, Kind};
@@ -45,7 +47,13 @@ async fn index() -> Option<NamedFile> {
     NamedFile::open(Path::new("static/index.html")).await.ok()
 }
 
-pub fn rocket(port: u16, order_book: Arc<OrderBook>, candle_store: Arc<CandleStore>) -> Rocket<Build> {
+pub fn rocket(
+    port: u16,
+    indexers: Indexers,
+    timeframes: Arc<Timeframes>,
+    markets: Arc<Markets>,
+    consistency: Arc<ConsistencyChecker>,
+) -> Rocket<Build> {
     let config = Config {
         address: Ipv4Addr::new(0, 0, 0, 0).into(),
         port,
@@ -57,11 +65,18 @@ pub fn rocket(port: u16, order_book: Arc<OrderBook>, candle_store: Arc<CandleSto
         async_graphql::EmptyMutation,
         async_graphql::EmptySubscription,
     )
-    .data(Arc::clone(&candle_store))
+    .data(Arc::clone(&timeframes))
+    .data(Arc::clone(&indexers.order_books))
+    .data(Arc::clone(&markets))
     .finish();
 
     rocket::custom(config)
-        .manage(candle_store)
+        .manage(timeframes)
+        .manage(indexers.order_books)
+        .manage(markets)
+        .manage(consistency)
+        .manage(indexers.dead_letters)
+        .manage(indexers.statuses)
         .manage(schema)
         .mount("/", routes![index]) // Добавляем маршрут для index.html
         .mount("/static", FileServer::from("static")) // Раздаём файлы из папки static